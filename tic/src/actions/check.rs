@@ -13,13 +13,12 @@ pub fn check(path: String) {
         }
     };
 
-    let mut syn = syntax::Syntax::new(&body.as_bytes());
+    let mut syn = syntax::Syntax::new(body.as_bytes());
     let res = syn.parse_all();
 
-    if let Ok(_) = res {
-        println!("No errors found.")
-    } else {
-        println!("{}", res.unwrap_err().dump_err(body.as_bytes()));
+    match res {
+        Ok(_) => println!("No errors found."),
+        Err(err) => println!("{}", err.dump_err(body.as_bytes())),
     }
 }
 
@@ -36,12 +35,8 @@ pub fn return_check(path: String) -> bool {
         }
     };
 
-    let mut syn = syntax::Syntax::new(&body.as_bytes());
+    let mut syn = syntax::Syntax::new(body.as_bytes());
     let res = syn.parse_all();
 
-    if let Ok(_) = res {
-        return true;
-    } else {
-        return false;
-    }
+    res.is_ok()
 }
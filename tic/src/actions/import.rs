@@ -0,0 +1,336 @@
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::{
+    ast::{Ast, Expr, Lit, Req, UseFrom},
+    err::ExecErr,
+    syntax::Syntax,
+};
+
+use super::local::Local;
+
+/// Replaces every `use` with the symbols of the imported template,
+/// namespaced as `ns::name`. Paths are relative to `dir`.
+pub fn expand(asts: Vec<Ast>, dir: &Path) -> Result<Vec<Ast>, ExecErr> {
+    let asts = expand_from(asts, dir, &mut Vec::new())?;
+
+    // Imported `req`s share the command line args, so a requirement
+    // declared the same way by several templates is only kept once.
+    let mut reqs: HashMap<String, Req> = HashMap::new();
+    let mut res = Vec::with_capacity(asts.len());
+    for ast in asts.into_iter() {
+        if let Ast::Req(ref req) = ast {
+            match reqs.get(&req.name) {
+                Some(prev) if same_req(prev, req) => continue,
+                Some(_) => {
+                    return Err(ExecErr::AlreadyExists(format!(
+                        "`{}` is required more than once with a different type or default",
+                        req.name
+                    )))
+                }
+                None => {
+                    reqs.insert(req.name.clone(), req.clone());
+                }
+            }
+        }
+        res.push(ast);
+    }
+    Ok(res)
+}
+
+/// Whether two declarations of a req ask for the same value,
+/// their docs may differ.
+fn same_req(a: &Req, b: &Req) -> bool {
+    a.ty == b.ty && a.optional == b.optional && a.default == b.default
+}
+
+fn expand_from(asts: Vec<Ast>, dir: &Path, seen: &mut Vec<String>) -> Result<Vec<Ast>, ExecErr> {
+    let mut res = Vec::new();
+    for ast in asts.into_iter() {
+        let use_ = match ast {
+            Ast::Use(use_) => use_,
+            ast => {
+                res.push(ast);
+                continue;
+            }
+        };
+
+        let (key, body, dir) = load(&use_.from, dir)?;
+        if seen.contains(&key) {
+            return Err(ExecErr::Import(key, "cyclic import".into()));
+        }
+        let mut syn = Syntax::new(body.as_bytes());
+        let asts = match syn.parse_all() {
+            Ok(asts) => asts,
+            Err(err) => return Err(ExecErr::Import(key, err.dump_err(body.as_bytes()))),
+        };
+
+        seen.push(key);
+        let asts = expand_from(asts, &dir, seen)?;
+        seen.pop();
+        // Only the importing template configures the output.
+        let asts = asts.into_iter().filter(|ast| !matches!(ast, Ast::Cfg(_)));
+        res.extend(qualify(asts.collect(), &use_.ns));
+    }
+    Ok(res)
+}
+
+/// Returns a key identifying the import, its body and the
+/// directory nested imports are relative to.
+fn load(from: &UseFrom, dir: &Path) -> Result<(String, String, PathBuf), ExecErr> {
+    match from {
+        UseFrom::Path(path) => {
+            let path = dir.join(path);
+            let err =
+                |err: std::io::Error| ExecErr::Import(path.display().to_string(), err.to_string());
+            let body = std::fs::read_to_string(&path).map_err(err)?;
+            // The same file can be reached through different paths.
+            let key = std::fs::canonicalize(&path).map_err(err)?;
+            let key = key.to_string_lossy().to_string();
+            let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+            Ok((key, body, dir))
+        }
+        UseFrom::Template(name) => {
+            let template = Local::new()
+                .and_then(|local| local.get(name))
+                .map_err(|err| ExecErr::Import(name.clone(), err.to_string()))?;
+            Ok((name.clone(), template.body, dir.to_path_buf()))
+        }
+    }
+}
+
+/// Prefixes the declarations of an imported template and every
+/// reference to them with `ns::`. Requirements stay global.
+fn qualify(mut asts: Vec<Ast>, ns: &str) -> Vec<Ast> {
+    let names = asts
+        .iter()
+        .filter_map(|ast| match ast {
+            Ast::Let(l) => Some(l.name.clone()),
+            Ast::Dir(d) => Some(d.alias.clone()),
+            Ast::File(f) => Some(f.alias.clone()),
+            _ => None,
+        })
+        .collect::<HashSet<String>>();
+    let q = Qualify { ns, names };
+
+    for ast in asts.iter_mut() {
+        match ast {
+            Ast::Let(l) => {
+                l.main = false;
+                q.name(&mut l.name);
+                let mut bound = l.params.iter().map(|p| p.name.clone()).collect();
+                q.params(&mut l.params);
+                q.annots(&mut l.annots, &mut bound);
+                q.exprs(&mut l.expr, &mut bound);
+            }
+            Ast::Dir(d) => {
+                d.main = false;
+                q.name(&mut d.alias);
+                let mut bound = d.params.iter().map(|p| p.name.clone()).collect();
+                q.params(&mut d.params);
+                q.annots(&mut d.annots, &mut bound);
+                q.exprs(&mut d.path, &mut bound);
+                q.dir_body(d, &mut bound);
+            }
+            Ast::File(f) => {
+                f.main = false;
+                q.name(&mut f.alias);
+                let mut bound = f.params.iter().map(|p| p.name.clone()).collect();
+                q.params(&mut f.params);
+                q.annots(&mut f.annots, &mut bound);
+                q.exprs(&mut f.path, &mut bound);
+                q.exprs(&mut f.content, &mut bound);
+            }
+            Ast::Req(r) => {
+                if let Some(default) = r.default.as_mut() {
+                    q.expr(default, &mut Vec::new());
+                }
+            }
+            Ast::Ref(_) | Ast::Lit(_) | Ast::Use(_) | Ast::Cfg(_) => {}
+        }
+    }
+    asts
+}
+
+struct Qualify<'a> {
+    ns: &'a str,
+    names: HashSet<String>,
+}
+
+impl Qualify<'_> {
+    fn name(&self, name: &mut String) {
+        *name = format!("{}::{}", self.ns, name);
+    }
+
+    fn params(&self, params: &mut [crate::ast::Param]) {
+        for param in params.iter_mut() {
+            if let Some(default) = param.default.as_mut() {
+                self.expr(default, &mut Vec::new());
+            }
+        }
+    }
+
+    fn annots(&self, annots: &mut [crate::ast::Annot], bound: &mut Vec<String>) {
+        for annot in annots.iter_mut() {
+            self.expr(&mut annot.val, bound);
+        }
+    }
+
+    /// The `let`s of a dir shadow the declarations of the template
+    /// inside of its body.
+    fn dir_body(&self, dir: &mut crate::ast::Dir, bound: &mut Vec<String>) {
+        let len = bound.len();
+        bound.extend(dir.lets.iter().map(|l| l.name.clone()));
+        for let_ in dir.lets.iter_mut() {
+            self.annots(&mut let_.annots, bound);
+            self.exprs(&mut let_.expr, bound);
+        }
+        self.exprs(&mut dir.children, bound);
+        bound.truncate(len);
+    }
+
+    fn exprs(&self, exprs: &mut [Expr], bound: &mut Vec<String>) {
+        for expr in exprs.iter_mut() {
+            self.expr(expr, bound);
+        }
+    }
+
+    /// `bound` holds the params and loop variables in scope,
+    /// which shadow the declarations of the template.
+    fn expr(&self, expr: &mut Expr, bound: &mut Vec<String>) {
+        match expr {
+            Expr::Ref(r) => {
+                if self.names.contains(&r.name) && !bound.contains(&r.name) {
+                    self.name(&mut r.name);
+                }
+                for (_, arg) in r.args.iter_mut() {
+                    self.expr(arg, bound);
+                }
+            }
+            Expr::Lit(Lit::BinOp(b)) => {
+                self.expr(&mut b.lhs, bound);
+                self.expr(&mut b.rhs, bound);
+            }
+            Expr::Lit(Lit::UnOp(u)) => self.expr(&mut u.expr, bound),
+            Expr::Lit(Lit::Pipe(p)) => {
+                self.expr(&mut p.val, bound);
+                for (_, arg) in p.args.iter_mut() {
+                    self.expr(arg, bound);
+                }
+            }
+            Expr::Lit(Lit::Dir(d)) => {
+                self.annots(&mut d.annots, bound);
+                self.exprs(&mut d.path, bound);
+                self.dir_body(d, bound);
+            }
+            Expr::Lit(Lit::File(f)) => {
+                self.annots(&mut f.annots, bound);
+                self.exprs(&mut f.path, bound);
+                self.exprs(&mut f.content, bound);
+            }
+            Expr::Lit(Lit::Link(l)) => {
+                self.annots(&mut l.annots, bound);
+                self.exprs(&mut l.path, bound);
+                self.exprs(&mut l.target, bound);
+            }
+            Expr::Lit(_) => {}
+            Expr::If(i) => {
+                self.expr(&mut i.cond, bound);
+                self.exprs(&mut i.then, bound);
+                self.exprs(&mut i.els, bound);
+            }
+            Expr::For(f) => {
+                self.expr(&mut f.iter, bound);
+                bound.push(f.var.clone());
+                self.exprs(&mut f.body, bound);
+                bound.pop();
+            }
+            Expr::List(l) => self.exprs(l, bound),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Ast, Expr, Lit, Ty};
+    use crate::err::ExecErr;
+    use crate::exec::{resolve::Resolve, Key, Scope, Syms};
+    use crate::syntax::Syntax;
+
+    #[test]
+    fn test_use_path() {
+        let tmp = crate::exec::fixture::temp_dir();
+        std::fs::create_dir_all(tmp.join("shared")).unwrap();
+        std::fs::write(
+            tmp.join("shared/base.ti"),
+            r#"
+                req author: str;
+                let year = "2024";
+                let license = {{MIT {$ year $} {$ author $}}};
+                main dir base { "LICENSE": license }
+            "#,
+        )
+        .unwrap();
+
+        let src = r#"
+            use "shared/base.ti";
+            req author: str;
+            let year = "1999";
+            let readme = {{{$ base::license $}, {$ year $}}};
+        "#;
+        let mut syn = Syntax::new(src.as_bytes());
+        let asts = super::expand(syn.parse_all().expect("Failed to parse"), &tmp)
+            .expect("Failed to expand imports");
+        let _ = std::fs::remove_dir_all(&tmp);
+
+        let args = vec![("author".to_string(), Expr::Lit(Lit::String("me".into())))];
+        let mut syms = Syms::new(args.clone());
+        syms.add_all_ast(asts).expect("Failed to add symbols");
+        assert!(syms.main().is_none());
+        assert!(syms.has(&Key("base::base".into(), Scope::Global)));
+
+        let sym = syms
+            .get(&Key("readme".into(), Scope::Global))
+            .unwrap()
+            .clone();
+        let res = match sym.val {
+            Ast::Let(l) => l.expr.resolve::<String>(&syms, &Ty::String, args),
+            _ => panic!("Expected let"),
+        };
+        assert_eq!(res.expect("Failed to resolve"), "MIT 2024 me, 1999");
+    }
+
+    #[test]
+    fn test_cyclic_use() {
+        let tmp = crate::exec::fixture::temp_dir();
+        std::fs::create_dir_all(tmp.join("sub")).unwrap();
+        std::fs::write(tmp.join("a.ti"), r#"use "sub/../a.ti";"#).unwrap();
+
+        let mut syn = Syntax::new(r#"use "./a.ti";"#.as_bytes());
+        let res = super::expand(syn.parse_all().expect("Failed to parse"), &tmp);
+        let _ = std::fs::remove_dir_all(&tmp);
+        assert!(matches!(res, Err(ExecErr::Import(_, err)) if err == "cyclic import"));
+    }
+
+    #[test]
+    fn test_conflicting_reqs() {
+        let tmp = crate::exec::fixture::temp_dir();
+        std::fs::write(tmp.join("base.ti"), "req author: str?;").unwrap();
+        let expand = |src: &str| {
+            let mut syn = Syntax::new(src.as_bytes());
+            super::expand(syn.parse_all().expect("Failed to parse"), &tmp)
+        };
+
+        let asts = expand(r#"use "base.ti"; req author: str?;"#).expect("Failed to expand");
+        assert_eq!(asts.len(), 1);
+        assert!(matches!(
+            expand(r#"use "base.ti"; req author: str;"#),
+            Err(ExecErr::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            expand("req port: int; req port: int = 80;"),
+            Err(ExecErr::AlreadyExists(_))
+        ));
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+}
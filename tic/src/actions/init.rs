@@ -1,7 +1,8 @@
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 
 use crate::{
-    ast::{Dir, Expr, File, Lit},
+    ast::{Annot, Dir, Expr, File, Link, Lit},
     err::ExecErr,
     exec::dump::Dump,
 };
@@ -16,7 +17,7 @@ pub fn init(name: Option<String>, publish: bool, local: bool, force: bool) {
             .to_string(),
     };
 
-    let res = into_dir(std::env::current_dir().unwrap(), true);
+    let res = capture(&std::env::current_dir().unwrap());
     if let Err(err) = res {
         println!("Error: {}", err);
         return;
@@ -25,7 +26,7 @@ pub fn init(name: Option<String>, publish: bool, local: bool, force: bool) {
     let dir = res.unwrap();
     let body = dir.dump();
     // let body = String::new();
-    let fi = match std::fs::File::create(&format!("{}.ti", name)) {
+    let fi = match std::fs::File::create(format!("{}.ti", name)) {
         Ok(fi) => fi,
         Err(err) => {
             println!("Error: {}", err);
@@ -44,48 +45,261 @@ pub fn init(name: Option<String>, publish: bool, local: bool, force: bool) {
     }
 }
 
-fn into_dir(path: PathBuf, main: bool) -> Result<Dir, ExecErr> {
-    if !path.exists() {
-        return Err(ExecErr::Io(std::io::Error::new(
-            std::io::ErrorKind::NotFound,
-            "Path does not exist",
-        )));
+/// Captures the tree at `root` as the main dir of a template.
+pub fn capture(root: &Path) -> Result<Dir, ExecErr> {
+    let mut capture = Capture {
+        root,
+        seen: HashMap::new(),
+    };
+    capture.dir(root.to_path_buf(), true)
+}
+
+struct Capture<'a> {
+    root: &'a Path,
+    /// Files with several hard links by inode, and where the
+    /// first of them was found.
+    seen: HashMap<(u64, u64), PathBuf>,
+}
+
+impl Capture<'_> {
+    fn dir(&mut self, path: PathBuf, main: bool) -> Result<Dir, ExecErr> {
+        if !path.exists() {
+            return Err(ExecErr::Io(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                "Path does not exist",
+            )));
+        }
+        let mut entries = path.read_dir()?.collect::<Result<Vec<_>, _>>()?;
+        // Sorted, so hard links point at a file captured before them.
+        entries.sort_by_key(|entry| entry.file_name());
+
+        let mut children = Vec::new();
+        for child in entries.into_iter() {
+            let path = child.path();
+            // Links are never followed, they may point at an ancestor.
+            let link = match child.file_type()?.is_symlink() {
+                true => Some(self.symlink(&path)?),
+                false => self.hard_link(&path)?,
+            };
+            if let Some(link) = link {
+                children.push(Expr::Lit(Lit::Link(link)));
+            } else if path.is_dir() {
+                children.push(Expr::Lit(Lit::Dir(self.dir(path, false)?)));
+            } else {
+                children.push(Expr::Lit(Lit::File(file(path)?)));
+            }
+        }
+        let name = path.file_name().unwrap().to_str().unwrap().to_string();
+        Ok(Dir {
+            main,
+            doc: None,
+            annots: mode(&path)?.into_iter().collect(),
+            params: Vec::new(),
+            path: vec![Expr::Lit(Lit::String(name.clone()))],
+            alias: name.clone(),
+            lets: Vec::new(),
+            scope: None,
+            children,
+        })
+    }
+
+    /// How deep the dir holding `path` is below the root.
+    fn depth(&self, path: &Path) -> usize {
+        path.parent()
+            .and_then(|parent| parent.strip_prefix(self.root).ok())
+            .map_or(0, |rel| rel.components().count())
+    }
+
+    /// Keeps a symlink as a link, absolute targets inside of the
+    /// root are made relative. Other targets are kept as they are.
+    fn symlink(&self, path: &Path) -> Result<Link, ExecErr> {
+        let target = std::fs::read_link(path)?;
+        let target = match target.strip_prefix(self.root) {
+            Ok(rel) if target.is_absolute() => {
+                PathBuf::from("../".repeat(self.depth(path))).join(rel)
+            }
+            _ => target,
+        };
+        Ok(link(path, &target.to_string_lossy(), Vec::new()))
     }
-    let mut children = Vec::new();
-    for child in path.read_dir()? {
-        let child = child?;
-        let path = child.path();
-        if path.is_dir() {
-            children.push(Expr::Lit(Lit::Dir(into_dir(path, false)?)));
-        } else {
-            children.push(Expr::Lit(Lit::File(into_file(path)?)));
+
+    /// A file that was captured before under another name becomes
+    /// a hard link to it.
+    #[cfg(unix)]
+    fn hard_link(&mut self, path: &Path) -> Result<Option<Link>, ExecErr> {
+        use std::os::unix::fs::MetadataExt;
+        let meta = std::fs::symlink_metadata(path)?;
+        if !meta.is_file() || meta.nlink() < 2 {
+            return Ok(None);
         }
+        let first = match self.seen.get(&(meta.dev(), meta.ino())) {
+            Some(first) => first,
+            None => {
+                self.seen
+                    .insert((meta.dev(), meta.ino()), path.to_path_buf());
+                return Ok(None);
+            }
+        };
+        let rel = first.strip_prefix(self.root).unwrap_or(first);
+        let target = PathBuf::from("../".repeat(self.depth(path))).join(rel);
+        let hard = Annot {
+            key: "hard".into(),
+            val: Expr::Lit(Lit::String("true".into())),
+        };
+        Ok(target.to_str().map(|target| link(path, target, vec![hard])))
     }
+
+    #[cfg(not(unix))]
+    fn hard_link(&mut self, _: &Path) -> Result<Option<Link>, ExecErr> {
+        Ok(None)
+    }
+}
+
+fn link(path: &Path, target: &str, annots: Vec<Annot>) -> Link {
     let name = path.file_name().unwrap().to_str().unwrap().to_string();
-    Ok(Dir {
-        main,
-        params: Vec::new(),
-        path,
-        alias: name.clone(),
-        children,
-    })
+    Link {
+        annots,
+        path: vec![Expr::Lit(Lit::String(name.clone()))],
+        alias: name,
+        target: vec![Expr::Lit(Lit::String(target.to_string()))],
+    }
 }
 
-fn into_file(path: PathBuf) -> Result<File, ExecErr> {
+fn file(path: PathBuf) -> Result<File, ExecErr> {
     if !path.exists() {
         return Err(ExecErr::Io(std::io::Error::new(
             std::io::ErrorKind::NotFound,
             "Path does not exist",
         )));
     }
-    let content = std::fs::read_to_string(path.clone())?;
     let name = path.file_name().unwrap().to_str().unwrap().to_string();
+    let mode = mode(&path)?;
+    let (content, mut annots) = match String::from_utf8(std::fs::read(path)?) {
+        Ok(content) => (content, Vec::new()),
+        // Binaries are kept as base64 and decoded when generating.
+        Err(err) => (
+            encode(err.as_bytes()),
+            vec![Annot {
+                key: "encoding".into(),
+                val: Expr::Lit(Lit::String("base64".into())),
+            }],
+        ),
+    };
+    annots.extend(mode);
 
     Ok(File {
         main: false,
+        doc: None,
+        annots,
         params: Vec::new(),
         alias: name.clone(),
-        path: name,
-        content: vec![Expr::Lit(Lit::String(content))],
+        path: vec![Expr::Lit(Lit::String(name))],
+        content: vec![Expr::Lit(Lit::Raw(content))],
     })
 }
+
+/// Encodes binary content as base64, wrapped at 76 columns.
+fn encode(bytes: &[u8]) -> String {
+    use base64::Engine;
+    let encoded = base64::engine::general_purpose::STANDARD.encode(bytes);
+    let lines = encoded
+        .as_bytes()
+        .chunks(76)
+        .map(|line| std::str::from_utf8(line).unwrap())
+        .collect::<Vec<&str>>();
+    format!("\n{}\n", lines.join("\n"))
+}
+
+/// Captures the mode of a file or dir as a `[mode=..]` annotation.
+/// It is always kept, without it the umask of whoever generates the
+/// template decides.
+#[cfg(unix)]
+fn mode(path: &std::path::Path) -> Result<Option<Annot>, ExecErr> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = std::fs::metadata(path)?.permissions().mode() & 0o7777;
+    Ok(Some(Annot {
+        key: "mode".into(),
+        val: Expr::Lit(Lit::String(format!("{:o}", mode))),
+    }))
+}
+
+#[cfg(not(unix))]
+fn mode(_: &std::path::Path) -> Result<Option<Annot>, ExecErr> {
+    Ok(None)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::exec::dump::Dump;
+    use crate::exec::{fixture::temp_dir, Exec, Syms};
+    use crate::syntax::Syntax;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_round_trip() {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+        let tmp = temp_dir();
+        let root = tmp.join("proj");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::fs::write(root.join("bin.dat"), [0, 159, 146, 150, 255]).unwrap();
+        std::fs::write(root.join("run.sh"), "#!/bin/sh\n").unwrap();
+        std::fs::set_permissions(root.join("run.sh"), std::fs::Permissions::from_mode(0o755))
+            .unwrap();
+        std::fs::write(root.join("text.txt"), "a }}# b {{ c {$ d $}").unwrap();
+        std::fs::set_permissions(
+            root.join("text.txt"),
+            std::fs::Permissions::from_mode(0o644),
+        )
+        .unwrap();
+        std::fs::set_permissions(root.join("sub"), std::fs::Permissions::from_mode(0o750)).unwrap();
+        std::fs::hard_link(root.join("text.txt"), root.join("sub/same.txt")).unwrap();
+        std::os::unix::fs::symlink("../text.txt", root.join("sub/link.txt")).unwrap();
+        std::os::unix::fs::symlink(root.join("sub"), root.join("abs")).unwrap();
+
+        let body = super::capture(&root).expect("Failed to capture").dump();
+        let mut syms = Syms::new(Vec::new());
+        let mut syn = Syntax::new(body.as_bytes());
+        syms.add_all_ast(syn.parse_all().expect("Failed to parse"))
+            .expect("Failed to add symbols");
+        let out = tmp.join("out");
+        Exec::run(&syms, syms.main().unwrap(), Vec::new(), out.clone()).expect("Failed to run");
+
+        let gen = out.join("proj");
+        let read = |path: &str| std::fs::read(gen.join(path)).unwrap();
+        let meta = |path: &str| std::fs::symlink_metadata(gen.join(path)).unwrap();
+        assert_eq!(read("bin.dat"), [0, 159, 146, 150, 255]);
+        assert_eq!(meta("run.sh").permissions().mode() & 0o777, 0o755);
+        assert_eq!(read("text.txt"), b"a }}# b {{ c {$ d $}");
+        // Modes are kept whatever the umask is when generating.
+        assert_eq!(meta("text.txt").permissions().mode() & 0o777, 0o644);
+        assert_eq!(meta("sub").permissions().mode() & 0o777, 0o750);
+        assert_eq!(meta("sub/same.txt").ino(), meta("text.txt").ino());
+        assert_eq!(
+            std::fs::read_link(gen.join("sub/link.txt")).unwrap(),
+            std::path::PathBuf::from("../text.txt")
+        );
+        assert_eq!(
+            std::fs::read_link(gen.join("abs")).unwrap(),
+            std::path::PathBuf::from("sub")
+        );
+        let _ = std::fs::remove_dir_all(&tmp);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_links_are_kept() {
+        let tmp = temp_dir();
+        let root = tmp.join("proj");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+        std::os::unix::fs::symlink("..", root.join("sub/up")).unwrap();
+        std::os::unix::fs::symlink(&tmp, root.join("tmp")).unwrap();
+        std::os::unix::fs::symlink("../outside.txt", root.join("ext.txt")).unwrap();
+
+        // Following the links to ancestors would never end.
+        let body = super::capture(&root).expect("Failed to capture").dump();
+        let _ = std::fs::remove_dir_all(&tmp);
+        assert!(body.contains(r#"link "up": "..""#));
+        assert!(body.contains(&format!(r#"link "tmp": "{}""#, tmp.display())));
+        assert!(body.contains(r#"link "ext.txt": "../outside.txt""#));
+    }
+}
@@ -1,55 +1,60 @@
+use std::path::PathBuf;
+
 use crate::{
-    ast::Expr,
+    ast::{Cfg, Expr, OnConflict},
     exec::{self, Syms},
     syntax::Syntax,
 };
 
-use super::local::Local;
-
-pub fn make(name: String, args: Vec<(String, Expr)>) {
-    let repo = match Local::new() {
-        Ok(local) => local,
-        Err(err) => {
-            println!("Sqlite Error: {}", err);
-            return;
-        }
-    };
-
-    let template = match repo.get(&name) {
-        Ok(template) => template,
-        Err(err) => {
-            println!("Sqlite Error: {}", err);
-            return;
-        }
-    };
-
-    let mut syms = Syms::new(args.clone());
+use super::{import, local::Local};
+
+/// Generates the template `name`, the error is what is printed
+/// before exiting with a failure.
+pub fn make(
+    name: String,
+    args: Vec<(String, Expr)>,
+    dry_run: bool,
+    on_conflict: Option<OnConflict>,
+    out: Option<PathBuf>,
+) -> Result<(), String> {
+    let repo = Local::new().map_err(|err| format!("Sqlite Error: {}", err))?;
+    let template = repo
+        .get(&name)
+        .map_err(|err| format!("Sqlite Error: {}", err))?;
+
+    let mut syms = Syms::new(args);
     let mut syn = Syntax::new(template.body.as_bytes());
-    let res = match syn.parse_all() {
-        Ok(res) => res,
-        Err(err) => {
-            println!("{}", err.dump_err(template.body.as_bytes()));
-            return;
-        }
-    };
-
-    if let Err(err) = syms.add_all_ast(res) {
-        println!("{:#?}", err);
-        return;
-    };
-
-    let main = match syms.main() {
-        Some(main) => main,
-        None => {
-            println!("No main function found");
-            return;
-        }
-    };
-
-    match exec::Exec::run(&syms, main, args) {
-        Ok(_) => println!("Done"),
-        Err(err) => {
-            println!("{:#?}", err);
-        }
+    let res = syn
+        .parse_all()
+        .map_err(|err| err.dump_err(template.body.as_bytes()))?;
+
+    // Templates in the store have no location, so imported
+    // paths are relative to where `make` is run.
+    let dir = std::env::current_dir().unwrap_or_default();
+    let res = import::expand(res, &dir).map_err(|err| err.to_string())?;
+    syms.add_all_ast(res).map_err(|err| err.to_string())?;
+
+    // The command line wins over the `cfg` block.
+    if let Some(on_conflict) = on_conflict {
+        syms.cfg = Some(Cfg {
+            on_conflict: Some(on_conflict),
+            ..syms.cfg().clone()
+        });
+    }
+
+    syms.check_args().map_err(|err| err.to_string())?;
+    let main = syms.main().ok_or("No main function found")?;
+
+    let out = out.unwrap_or_else(|| syms.out());
+    let plan =
+        exec::Exec::plan(&syms, main, syms.args.clone(), out).map_err(|err| err.to_string())?;
+    if dry_run {
+        print!("{}", plan);
+        return Ok(());
+    }
+    for resolved in plan.apply().map_err(|err| err.to_string())?.iter() {
+        println!("{}", resolved);
     }
+    println!("Done");
+    Ok(())
 }
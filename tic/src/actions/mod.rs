@@ -1,4 +1,5 @@
 pub mod check;
+pub mod import;
 pub mod init;
 pub mod local;
 pub mod make;
@@ -32,6 +32,12 @@ pub fn repl() {
                 inp.clear();
                 continue;
             }
+            Res::Err(err) => {
+                println!("{}", err);
+                buf.clear();
+                inp.clear();
+                continue;
+            }
             _ => {
                 buf.clear();
                 inp.clear();
@@ -40,7 +46,7 @@ pub fn repl() {
         }
 
         let src = buf.clone();
-        let mut syn = syntax::Syntax::new(&src.as_bytes());
+        let mut syn = syntax::Syntax::new(src.as_bytes());
         let res = syn.parse_all();
         println!("{:#?}", res);
 
@@ -73,11 +79,11 @@ enum Res {
 }
 
 fn run(syms: &mut Syms, cmd: &str) -> Res {
-    if cmd == "" {
+    if cmd.is_empty() {
         return Res::Commit;
     }
     let parts = cmd.split(' ').collect::<Vec<&str>>();
-    if parts.len() == 0 {
+    if parts.is_empty() {
         return Res::None;
     }
 
@@ -96,11 +102,11 @@ fn run(syms: &mut Syms, cmd: &str) -> Res {
             let sym = syms.get(&Key(parts[1].to_string(), Scope::Global));
             println!("{:#?}", sym);
             if let Some(sy) = sym {
-                let res = Exec::run(syms, sy.val.clone(), Vec::new());
+                let res = Exec::run(syms, sy.val.clone(), Vec::new(), syms.out());
                 println!("{:#?}", res);
                 return match res {
                     Ok(_) => Res::DidAction,
-                    Err(err) => Res::Err(err.into()),
+                    Err(err) => Res::Err(err),
                 };
             }
             Res::NotFound
@@ -1,10 +1,11 @@
 use crate::err::ExecErr;
 
-#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Ty {
     String,
     Int,
-    List,
+    /// A list with its element type, `Unknown` if not declared.
+    List(Box<Ty>),
     Dir,
     File,
     Unknown,
@@ -15,7 +16,7 @@ impl From<&str> for Ty {
         match s {
             "str" => Ty::String,
             "int" => Ty::Int,
-            "list" => Ty::List,
+            "list" => Ty::List(Box::new(Ty::Unknown)),
             "dir" => Ty::Dir,
             "file" => Ty::File,
             _ => Ty::Unknown,
@@ -23,12 +24,23 @@ impl From<&str> for Ty {
     }
 }
 
+impl Ty {
+    /// The value of an optional requirement that was not given.
+    pub fn empty(&self) -> Lit {
+        match self {
+            Ty::List(_) => Lit::List(Vec::new()),
+            _ => Lit::String(String::new()),
+        }
+    }
+}
+
 impl std::fmt::Display for Ty {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             Ty::String => f.write_str("str"),
             Ty::Int => f.write_str("int"),
-            Ty::List => f.write_str("list"),
+            Ty::List(elem) if **elem == Ty::Unknown => f.write_str("list"),
+            Ty::List(elem) => write!(f, "list<{}>", elem),
             Ty::Dir => f.write_str("dir"),
             Ty::File => f.write_str("file"),
             Ty::Unknown => f.write_str("unknown"),
@@ -44,9 +56,33 @@ pub enum Ast {
     Dir(Dir),
     File(File),
     Lit(String),
+    Use(Use),
+    Cfg(Cfg),
 }
 
 impl Ast {
+    pub fn set_doc(&mut self, doc: String) {
+        match self {
+            Ast::Let(l) => l.doc = Some(doc),
+            Ast::Dir(d) => d.doc = Some(doc),
+            Ast::File(f) => f.doc = Some(doc),
+            Ast::Req(r) => r.doc = Some(doc),
+            _ => {}
+        }
+    }
+
+    /// Attaches annotations, returns false for declarations
+    /// that cannot be annotated.
+    pub fn set_annots(&mut self, annots: Vec<Annot>) -> bool {
+        match self {
+            Ast::Let(l) => l.annots = annots,
+            Ast::Dir(d) => d.annots = annots,
+            Ast::File(f) => f.annots = annots,
+            _ => return false,
+        }
+        true
+    }
+
     pub fn set_main(&mut self) {
         match self {
             Ast::Let(l) => l.main = true,
@@ -64,6 +100,8 @@ pub enum AstKind {
     Dir,
     File,
     Lit,
+    Use,
+    Cfg,
 }
 
 impl std::fmt::Display for AstKind {
@@ -74,6 +112,8 @@ impl std::fmt::Display for AstKind {
             AstKind::Dir => f.write_str("dir"),
             AstKind::File => f.write_str("file"),
             AstKind::Lit => f.write_str("lit"),
+            AstKind::Use => f.write_str("use"),
+            AstKind::Cfg => f.write_str("cfg"),
         }
     }
 }
@@ -88,6 +128,7 @@ impl Ast {
             Ast::Req(_) => false,
             Ast::Ref(_) => false,
             Ast::Lit(_) => false,
+            Ast::Use(_) | Ast::Cfg(_) => false,
         }
     }
 
@@ -103,29 +144,31 @@ impl Ast {
         match self {
             Ast::Let(l) => l.name.clone(),
             Ast::Req(r) => r.name.clone(),
-            Ast::Dir(d) => d.path.to_str().unwrap().to_string(),
-            Ast::File(f) => f.path.clone(),
+            Ast::Dir(d) => crate::exec::dump::dump_path(&d.path),
+            Ast::File(f) => crate::exec::dump::dump_path(&f.path),
             Ast::Ref(r) => r.name.clone(),
+            Ast::Use(u) => u.ns.clone(),
+            Ast::Cfg(_) => "cfg".into(),
             _ => unreachable!("A Literal cannot be inserted as a symbol."),
         }
     }
 
-    pub fn params(&self) -> Vec<(String, Ty)> {
+    pub fn params(&self) -> Vec<Param> {
         match self {
             Ast::Let(l) => l.params.clone(),
             Ast::Dir(d) => d.params.clone(),
             Ast::File(f) => f.params.clone(),
-            Ast::Req(_) | Ast::Ref(_) | Ast::Lit(_) => Vec::new(),
+            Ast::Req(_) | Ast::Ref(_) | Ast::Lit(_) | Ast::Use(_) | Ast::Cfg(_) => Vec::new(),
         }
     }
 
     pub fn ty(&self) -> Ty {
         match self {
-            Ast::Let(l) => l.ty,
-            Ast::Req(r) => r.ty,
+            Ast::Let(l) => l.ty.clone(),
+            Ast::Req(r) => r.ty.clone(),
             Ast::Dir(_) => Ty::Dir,
             Ast::File(_) => Ty::File,
-            Ast::Ref(_) => Ty::Unknown,
+            Ast::Ref(_) | Ast::Use(_) | Ast::Cfg(_) => Ty::Unknown,
             Ast::Lit(_) => Ty::String,
         }
     }
@@ -138,6 +181,8 @@ impl Ast {
             Ast::File(_) => AstKind::File,
             Ast::Ref(_) => AstKind::Req,
             Ast::Lit(_) => AstKind::Lit,
+            Ast::Use(_) => AstKind::Use,
+            Ast::Cfg(_) => AstKind::Cfg,
         }
     }
 }
@@ -150,6 +195,8 @@ impl From<&AstKind> for AstKind {
             AstKind::Dir => AstKind::Dir,
             AstKind::File => AstKind::File,
             AstKind::Lit => AstKind::Lit,
+            AstKind::Use => AstKind::Use,
+            AstKind::Cfg => AstKind::Cfg,
         }
     }
 }
@@ -157,41 +204,157 @@ impl From<&AstKind> for AstKind {
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Dir {
     pub main: bool,
-    pub params: Vec<(String, Ty)>,
-    pub path: std::path::PathBuf,
+    /// The `///` comment in front of the declaration.
+    pub doc: Option<String>,
+    pub annots: Vec<Annot>,
+    pub params: Vec<Param>,
+    /// Resolved against the args when executed, e.g. `src/{$ module $}`.
+    pub path: Vec<Expr>,
     pub alias: String,
+    /// `let`s declared in the body, only visible inside of it.
+    pub lets: Vec<Let>,
+    /// Scope of `lets`, assigned when the dir is added to the symbols.
+    pub scope: Option<String>,
     pub children: Vec<Expr>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct File {
     pub main: bool,
-    pub params: Vec<(String, Ty)>,
-    pub path: String,
+    /// The `///` comment in front of the declaration.
+    pub doc: Option<String>,
+    pub annots: Vec<Annot>,
+    pub params: Vec<Param>,
+    /// Resolved against the args when executed, e.g. `"{$ name $}.go"`.
+    pub path: Vec<Expr>,
     pub alias: String,
     pub content: Vec<Expr>,
 }
 
-impl File {
-    pub fn path(&self, parent: std::path::PathBuf) -> std::path::PathBuf {
-        parent.join(&self.path)
+/// A symbolic link inside of a dir, `link "rustfmt.toml": "../rustfmt.toml"`.
+/// The target is relative to the dir holding the link.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Link {
+    pub annots: Vec<Annot>,
+    pub path: Vec<Expr>,
+    pub alias: String,
+    pub target: Vec<Expr>,
+}
+
+/// A `[key=value]` annotation in front of a `let`, `file` or `dir`,
+/// e.g. `[lang=go]` or `[skip_if=!ci]`. Only `skip_if` takes an
+/// expression, other values are kept as plain strings.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Annot {
+    pub key: String,
+    pub val: Expr,
+}
+
+impl Annot {
+    pub fn get<'a>(annots: &'a [Annot], key: &str) -> Option<&'a Expr> {
+        annots.iter().rev().find(|a| a.key == key).map(|a| &a.val)
     }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Let {
     pub main: bool,
+    /// The `///` comment in front of the declaration.
+    pub doc: Option<String>,
+    pub annots: Vec<Annot>,
     pub name: String,
-    pub params: Vec<(String, Ty)>,
+    pub params: Vec<Param>,
     pub ty: Ty,
     pub expr: Vec<Expr>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct Req {
+    pub doc: Option<String>,
+    pub name: String,
+    pub ty: Ty,
+    /// Declared with `?`, may be left out without a default.
+    pub optional: bool,
+    pub default: Option<Expr>,
+}
+
+/// The `cfg { .. }` block of a template, unset keys keep the
+/// generated output as written.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Cfg {
+    /// Each indent level of generated files is replaced by this, e.g. `[indent=4s]`.
+    pub indent: Option<String>,
+    /// `[eol=lf]` or `[eol=crlf]`.
+    pub eol: Option<Eol>,
+    /// Whether files end with exactly one newline or none, `[final_newline=true]`.
+    pub final_newline: Option<bool>,
+    /// What to do with files that already exist, `[on_conflict=skip]`.
+    pub on_conflict: Option<OnConflict>,
+    /// Whether `{{ .. }}` bodies are dedented, `[dedent=false]` keeps
+    /// them as written. Only applies to bodies after the `cfg` block.
+    pub dedent: Option<bool>,
+    /// Whether multi-line values are indented to their insertion column.
+    pub reindent: Option<bool>,
+    /// Directory the main dir or file is generated in, `[out=build]`.
+    pub out: Option<std::path::PathBuf>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Eol {
+    Lf,
+    Crlf,
+}
+
+/// What to do with a file that already exists, `fail` leaves
+/// everything as it was.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum OnConflict {
+    #[default]
+    Fail,
+    Skip,
+    Overwrite,
+    /// Asks for every file.
+    Prompt,
+    /// Renames the existing file to `<name>.bak` first.
+    Backup,
+}
+
+impl std::str::FromStr for OnConflict {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(OnConflict::Fail),
+            "skip" => Ok(OnConflict::Skip),
+            "overwrite" => Ok(OnConflict::Overwrite),
+            "prompt" => Ok(OnConflict::Prompt),
+            "backup" => Ok(OnConflict::Backup),
+            _ => Err("fail, skip, overwrite, prompt or backup".into()),
+        }
+    }
+}
+
+/// `use "other.ti" as ns;` or `use template name as ns;`, the
+/// symbols of the imported template are available as `ns::name`.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Use {
+    pub from: UseFrom,
+    pub ns: String,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum UseFrom {
+    /// Relative to the importing template.
+    Path(String),
+    /// A template in the local store.
+    Template(String),
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Param {
     pub name: String,
     pub ty: Ty,
-    pub expr: String,
+    pub default: Option<Expr>,
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -199,6 +362,8 @@ pub enum Expr {
     Ref(Ref),
     Lit(Lit),
     If(If),
+    For(For),
+    List(Vec<Expr>),
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
@@ -210,11 +375,18 @@ pub struct Ref {
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct If {
-    pub cond: BinOp,
+    pub cond: Box<Expr>,
     pub then: Vec<Expr>,
     pub els: Vec<Expr>,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct For {
+    pub var: String,
+    pub iter: Box<Expr>,
+    pub body: Vec<Expr>,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct BinOp {
     pub op: Op,
@@ -222,12 +394,54 @@ pub struct BinOp {
     pub rhs: Box<Expr>,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct UnOp {
+    pub op: Op,
+    pub expr: Box<Expr>,
+}
+
+/// `val | filter(args)`, filters are always builtins so names in
+/// scope cannot shadow them.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Pipe {
+    pub val: Box<Expr>,
+    pub filter: String,
+    pub args: Vec<(String, Expr)>,
+}
+
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Op {
     Add,
+    Sub,
     Mul,
+    Div,
     Eq,
     Neq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
+    Not,
+    Index,
+}
+
+impl Op {
+    /// Binding power of a binary operator, higher binds tighter.
+    pub fn prec(&self) -> u8 {
+        use Op::*;
+        match self {
+            Or => 1,
+            And => 2,
+            Eq | Neq => 3,
+            Lt | Gt | Le | Ge => 4,
+            Add | Sub => 5,
+            Mul | Div => 6,
+            Not => 7,
+            Index => 8,
+        }
+    }
 }
 
 impl std::fmt::Display for Op {
@@ -235,9 +449,19 @@ impl std::fmt::Display for Op {
         use Op::*;
         match self {
             Add => f.write_str("+"),
+            Sub => f.write_str("-"),
             Mul => f.write_str("*"),
+            Div => f.write_str("/"),
             Eq => f.write_str("=="),
             Neq => f.write_str("!="),
+            Lt => f.write_str("<"),
+            Gt => f.write_str(">"),
+            Le => f.write_str("<="),
+            Ge => f.write_str(">="),
+            And => f.write_str("&&"),
+            Or => f.write_str("||"),
+            Not => f.write_str("!"),
+            Index => f.write_str("[]"),
         }
     }
 }
@@ -246,9 +470,16 @@ impl std::fmt::Display for Op {
 pub enum Lit {
     Int(String),
     String(String),
+    /// A `r#{{ .. }}#` body, copied as is without applying the `cfg`.
+    Raw(String),
+    Bool(bool),
     Dir(Dir),
     File(File),
+    Link(Link),
+    List(Vec<Lit>),
     BinOp(BinOp),
+    UnOp(UnOp),
+    Pipe(Pipe),
 }
 
 impl TryFrom<Lit> for String {
@@ -256,12 +487,30 @@ impl TryFrom<Lit> for String {
 
     fn try_from(lit: Lit) -> Result<Self, Self::Error> {
         match lit {
-            Lit::String(s) => Ok(s),
+            Lit::String(s) | Lit::Raw(s) => Ok(s),
+            Lit::Int(i) => Ok(i),
+            Lit::Bool(b) => Ok(b.to_string()),
+            Lit::List(l) => Ok(l
+                .into_iter()
+                .map(String::try_from)
+                .collect::<Result<Vec<String>, ExecErr>>()?
+                .join(", ")),
             _ => Err(ExecErr::InvalidType("".into(), "".into())),
         }
     }
 }
 
+impl TryFrom<Lit> for Vec<Lit> {
+    type Error = ExecErr;
+
+    fn try_from(lit: Lit) -> Result<Self, Self::Error> {
+        match lit {
+            Lit::List(l) => Ok(l),
+            _ => Err(ExecErr::InvalidType("".into(), "list".into())),
+        }
+    }
+}
+
 impl TryFrom<Lit> for File {
     type Error = ExecErr;
 
@@ -289,16 +538,27 @@ impl TryFrom<Lit> for bool {
 
     fn try_from(lit: Lit) -> Result<Self, Self::Error> {
         match lit {
-            Lit::BinOp(bin) => match bin.op {
-                Op::Eq => Ok(bin.lhs == bin.rhs),
-                Op::Neq => Ok(bin.lhs != bin.rhs),
-                _ => Err(ExecErr::InvalidType("".into(), "".into())),
-            },
-            _ => Err(ExecErr::InvalidType("".into(), "".into())),
+            Lit::Bool(b) => Ok(b),
+            Lit::String(s) | Lit::Raw(s) => Ok(!s.is_empty() && s != "false"),
+            Lit::Int(i) => Ok(i.parse::<i64>() != Ok(0)),
+            Lit::List(l) => Ok(!l.is_empty()),
+            _ => Err(ExecErr::InvalidType("".into(), "bool".into())),
         }
     }
 }
 
+/// A fully resolved value, for places that need to inspect
+/// a literal rather than render it, e.g. operator operands.
+pub struct LitValue(pub Lit);
+
+impl TryFrom<Lit> for LitValue {
+    type Error = ExecErr;
+
+    fn try_from(lit: Lit) -> Result<Self, Self::Error> {
+        Ok(LitValue(lit))
+    }
+}
+
 pub enum LitExecutable {
     String(String),
     File(File),
@@ -310,10 +570,10 @@ impl TryFrom<Lit> for LitExecutable {
 
     fn try_from(lit: Lit) -> Result<Self, Self::Error> {
         match lit {
-            Lit::String(s) => Ok(LitExecutable::String(s)),
+            Lit::String(s) | Lit::Raw(s) => Ok(LitExecutable::String(s)),
             Lit::File(f) => Ok(LitExecutable::File(f)),
             Lit::Dir(d) => Ok(LitExecutable::Dir(d)),
-            l => Err(ExecErr::InvalidExecutable),
+            _ => Err(ExecErr::InvalidExecutable),
         }
     }
 }
@@ -21,7 +21,7 @@ pub fn is_ident(st: &str) -> bool {
             return false;
         }
     }
-    while let Some(ch) = chars.next() {
+    for ch in chars {
         if !ch.is_alphanumeric() && ch != '_' && ch != '.' {
             return false;
         }
@@ -32,7 +32,7 @@ pub fn is_ident(st: &str) -> bool {
 #[inline]
 pub fn is_all_num(st: &str) -> bool {
     for ch in st.chars() {
-        if !ch.is_digit(10) {
+        if !ch.is_ascii_digit() {
             return false;
         }
     }
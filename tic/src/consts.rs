@@ -1,7 +1,7 @@
-pub const RED: &'static str = "\x1b[31m";
-pub const BLUE: &'static str = "\x1b[34m";
-pub const GREEN: &'static str = "\x1b[32m";
-pub const RESET: &'static str = "\x1b[0m";
+pub const RED: &str = "\x1b[31m";
+pub const BLUE: &str = "\x1b[34m";
+pub const GREEN: &str = "\x1b[32m";
+pub const RESET: &str = "\x1b[0m";
 
-pub const DB_DIR: &'static str = ".tipis/";
-pub const DB_PATH: &'static str = ".tipis/tipis.db";
+pub const DB_DIR: &str = ".tipis/";
+pub const DB_PATH: &str = ".tipis/tipis.db";
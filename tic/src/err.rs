@@ -11,6 +11,7 @@ pub enum LxErrKind {
     InvalidUtf8,
     UnexpectedEOF,
     UnterminatedString,
+    UnterminatedComment,
 }
 
 #[derive(Debug, thiserror::Error, PartialEq, Clone)]
@@ -25,6 +26,8 @@ pub enum LxErr {
     UnexpectedEOF(String),
     #[error("Lex: Unterminated String :: {0}")]
     Unterminated(String),
+    #[error("Lex: Unterminated Comment :: {0}")]
+    UnterminatedComment(String),
 }
 
 #[cfg_attr(test, derive(PartialEq))]
@@ -46,6 +49,9 @@ pub enum ExecErr {
     #[error("Exec: AlreadyExists :: {0}")]
     AlreadyExists(String),
 
+    #[error("Exec: AlreadyExists, nothing was written ::\n  {}", .0.join("\n  "))]
+    Conflicts(Vec<String>),
+
     #[error("Program cannot contain multiple main declarations")]
     MultipleMain,
 
@@ -58,6 +64,12 @@ pub enum ExecErr {
     #[error("Exec: InvalidArgument :: {0}")]
     InvalidArgument(String),
 
+    #[error("Invalid arguments:\n  {}", .0.join("\n  "))]
+    InvalidArgs(Vec<String>),
+
+    #[error("Exec: Import :: {0}\n{1}")]
+    Import(String, String),
+
     #[error("Exec: InvalidExecutable")]
     InvalidExecutable,
 
@@ -125,13 +137,13 @@ where
             end_ix += 1;
         }
 
-        for i in start_ix..=end_ix {
+        for (i, ch) in src.iter().enumerate().take(end_ix + 1).skip(start_ix) {
             if i >= self.src.bix && i < self.src.bix + self.src.len {
                 line.push_str(consts::RED);
             } else {
                 line.push_str(consts::GREEN);
             }
-            line.push(src[i] as char);
+            line.push(*ch as char);
             if i == self.src.bix {
                 line.push_str(consts::RESET);
             }
@@ -158,7 +170,7 @@ where
     pub fn dump_err(&self, src: &'a [u8]) -> String {
         let mut err = String::new();
         err.push_str(&self.display_line(src));
-        err.push_str("\n");
+        err.push('\n');
         err.push_str(&self.err.to_string());
         err
     }
@@ -180,7 +192,7 @@ impl<'a> Trace<'a, SynErr> {
 
 impl<'a, E: Error + 'static> std::fmt::Display for Trace<'a, E> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.err.to_string())
+        write!(f, "{}", self.err)
     }
 }
 
@@ -0,0 +1,135 @@
+use crate::ast::Lit;
+use crate::err::ExecErr;
+
+use super::dump::Dump;
+
+pub type Builtin = fn(Vec<Lit>) -> Result<Lit, ExecErr>;
+
+/// Functions available to every template. Called by name they are
+/// only looked up if no symbol of the same name exists, as filters
+/// they always are.
+pub fn lookup(name: &str) -> Option<Builtin> {
+    Some(match name {
+        "len" => len,
+        "join" => join,
+        "first" => first,
+        "last" => last,
+        "upper" => |args| text("upper", args, str::to_uppercase),
+        "lower" => |args| text("lower", args, str::to_lowercase),
+        "trim" => |args| text("trim", args, |s| s.trim().to_owned()),
+        "capitalize" => |args| text("capitalize", args, capitalize),
+        "snake_case" => |args| text("snake_case", args, |s| words(s).join("_")),
+        "kebab_case" => |args| text("kebab_case", args, |s| words(s).join("-")),
+        "pascal_case" => |args| {
+            text("pascal_case", args, |s| {
+                words(s).iter().map(|w| capitalize(w)).collect()
+            })
+        },
+        "camel_case" => |args| {
+            text("camel_case", args, |s| {
+                let mut words = words(s).into_iter();
+                let first = words.next().unwrap_or_default();
+                words.fold(first, |buf, w| buf + &capitalize(&w))
+            })
+        },
+        _ => return None,
+    })
+}
+
+fn len(args: Vec<Lit>) -> Result<Lit, ExecErr> {
+    let [val] = arity::<1>("len", args)?;
+    let len = match val {
+        Lit::String(s) | Lit::Raw(s) => s.chars().count(),
+        val => Vec::<Lit>::try_from(val)?.len(),
+    };
+    Ok(Lit::Int(len.to_string()))
+}
+
+fn join(args: Vec<Lit>) -> Result<Lit, ExecErr> {
+    let [list, sep] = arity::<2>("join", args)?;
+    let sep = String::try_from(sep)?;
+    let items = Vec::<Lit>::try_from(list)?
+        .into_iter()
+        .map(String::try_from)
+        .collect::<Result<Vec<String>, ExecErr>>()?;
+    Ok(Lit::String(items.join(&sep)))
+}
+
+fn first(args: Vec<Lit>) -> Result<Lit, ExecErr> {
+    let [list] = arity::<1>("first", args)?;
+    index(list, 0)
+}
+
+fn last(args: Vec<Lit>) -> Result<Lit, ExecErr> {
+    let [list] = arity::<1>("last", args)?;
+    index(list, -1)
+}
+
+/// Applies `f` to the single string argument of a filter.
+fn text(name: &str, args: Vec<Lit>, f: fn(&str) -> String) -> Result<Lit, ExecErr> {
+    let [val] = arity::<1>(name, args)?;
+    Ok(Lit::String(f(&String::try_from(val)?)))
+}
+
+fn capitalize(s: &str) -> String {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+/// Splits a name into lowercase words, on anything that is not
+/// alphanumeric and on case changes, so `myApp`, `my-app` and
+/// `HTTPServer` give `[my, app]`, `[my, app]` and `[http, server]`.
+fn words(s: &str) -> Vec<String> {
+    let chars = s.chars().collect::<Vec<char>>();
+    let mut words = Vec::new();
+    let mut buf = String::new();
+    for (ix, &ch) in chars.iter().enumerate() {
+        if !ch.is_alphanumeric() {
+            if !buf.is_empty() {
+                words.push(std::mem::take(&mut buf));
+            }
+            continue;
+        }
+        let prev = ix.checked_sub(1).map(|i| chars[i]);
+        let next = chars.get(ix + 1);
+        let boundary = ch.is_uppercase()
+            && match prev {
+                Some(p) if p.is_lowercase() || p.is_numeric() => true,
+                Some(p) if p.is_uppercase() => next.is_some_and(|n| n.is_lowercase()),
+                _ => false,
+            };
+        if boundary && !buf.is_empty() {
+            words.push(std::mem::take(&mut buf));
+        }
+        buf.extend(ch.to_lowercase());
+    }
+    if !buf.is_empty() {
+        words.push(buf);
+    }
+    words
+}
+
+/// Indexes into a list, negative indices count from the end.
+pub fn index(list: Lit, ix: i64) -> Result<Lit, ExecErr> {
+    let dump = list.dump();
+    let mut items = Vec::<Lit>::try_from(list)?;
+    let len = items.len() as i64;
+    let pos = if ix < 0 { len + ix } else { ix };
+    if pos < 0 || pos >= len {
+        return Err(ExecErr::InvalidArgument(format!(
+            "index {} out of bounds for {}",
+            ix, dump
+        )));
+    }
+    Ok(items.swap_remove(pos as usize))
+}
+
+fn arity<const N: usize>(name: &str, args: Vec<Lit>) -> Result<[Lit; N], ExecErr> {
+    let got = args.len();
+    args.try_into().map_err(|_| {
+        ExecErr::InvalidArgument(format!("{} expects {} argument(s), got {}", name, N, got))
+    })
+}
@@ -1,4 +1,4 @@
-use crate::ast::{Dir, Expr, File, Lit, Ref, Ty};
+use crate::ast::{Annot, Dir, Expr, File, For, If, Lit, Op, Param, Ref, Ty};
 
 pub trait Dump {
     fn dump(&self) -> String;
@@ -6,9 +6,14 @@ pub trait Dump {
 
 impl Dump for Dir {
     fn dump(&self) -> String {
-        let mut buf = String::from("dir ");
+        let mut buf = dump_doc(&self.doc);
+        buf.push_str(&self.annots.dump());
+        if self.main {
+            buf.push_str("main ");
+        }
+        buf.push_str("dir ");
         buf.push_str(&self.alias);
-        if self.params.len() > 0 {
+        if !self.params.is_empty() {
             buf.push_str(" (");
             for (ix, param) in self.params.iter().enumerate() {
                 if ix > 0 {
@@ -16,22 +21,27 @@ impl Dump for Dir {
                 }
                 buf.push_str(&param.dump());
             }
-            buf.push_str(")");
+            buf.push(')');
         }
         buf.push_str(": ");
-        buf.push_str(&self.path.to_str().unwrap());
+        buf.push_str(&dump_path(&self.path));
         buf.push_str(" {");
         buf.push_str(&self.children.dump());
-        buf.push_str("}");
+        buf.push_str(" }");
         buf
     }
 }
 
 impl Dump for File {
     fn dump(&self) -> String {
-        let mut buf = String::from("file ");
+        let mut buf = dump_doc(&self.doc);
+        buf.push_str(&self.annots.dump());
+        if self.main {
+            buf.push_str("main ");
+        }
+        buf.push_str("file ");
         buf.push_str(&self.alias);
-        if self.params.len() > 0 {
+        if !self.params.is_empty() {
             buf.push_str(" (");
             for (ix, param) in self.params.iter().enumerate() {
                 if ix > 0 {
@@ -39,20 +49,85 @@ impl Dump for File {
                 }
                 buf.push_str(&param.dump());
             }
-            buf.push_str(")");
+            buf.push(')');
         }
         buf.push_str(": ");
-        buf.push_str(&self.path);
-        buf.push_str("{\"");
-        buf.push_str(&self.content.dump());
-        buf.push_str("\"}");
+        buf.push_str(&dump_path(&self.path));
+        buf.push(' ');
+        buf.push_str(&dump_body(&self.content));
         buf
     }
 }
 
-impl Dump for &(String, Ty) {
+impl Dump for Vec<Annot> {
     fn dump(&self) -> String {
-        format!("{}: {}", self.0, self.1.dump())
+        let mut buf = String::new();
+        for annot in self.iter() {
+            let val = match annot.val {
+                Expr::Lit(Lit::String(ref s)) if is_bare(s) => s.clone(),
+                ref val => val.dump(),
+            };
+            buf.push_str(&format!("[{}={}] ", annot.key, val));
+        }
+        buf
+    }
+}
+
+fn dump_doc(doc: &Option<String>) -> String {
+    match doc {
+        Some(doc) => doc.lines().map(|line| format!("/// {}\n", line)).collect(),
+        None => String::new(),
+    }
+}
+
+/// Dumps file content. Literal content becomes a raw body, with as
+/// many `#` as needed for it to be read back verbatim.
+fn dump_body(content: &[Expr]) -> String {
+    let mut text = String::new();
+    for expr in content.iter() {
+        match expr {
+            Expr::Lit(Lit::String(s) | Lit::Raw(s)) => text.push_str(s),
+            _ => return content.to_vec().dump(),
+        }
+    }
+    dump_raw(&text)
+}
+
+/// Dumps text as a raw body that reads back as the same text.
+fn dump_raw(text: &str) -> String {
+    let mut hashes = String::from("#");
+    while text.contains(&format!("}}}}{}", hashes)) {
+        hashes.push('#');
+    }
+    format!("r{0}{{{{{1}}}}}{0}", hashes, text)
+}
+
+/// Whether an annotation value can be written without quotes.
+fn is_bare(s: &str) -> bool {
+    !s.is_empty()
+        && s.chars()
+            .all(|ch| ch.is_alphanumeric() || matches!(ch, '_' | '.' | '/' | '-'))
+}
+
+/// Dumps a file or dir name the way it is written in a template.
+pub fn dump_path(path: &[Expr]) -> String {
+    let mut buf = String::new();
+    for expr in path.iter() {
+        match expr {
+            Expr::Lit(Lit::String(s)) => buf.push_str(s),
+            Expr::Ref(r) if r.args.is_empty() => buf.push_str(&format!("{{$ {} $}}", r.name)),
+            expr => buf.push_str(&format!("{{$ {} $}}", expr.dump())),
+        }
+    }
+    buf
+}
+
+impl Dump for &Param {
+    fn dump(&self) -> String {
+        match &self.default {
+            Some(default) => format!("{}: {} = {}", self.name, self.ty.dump(), default.dump()),
+            None => format!("{}: {}", self.name, self.ty.dump()),
+        }
     }
 }
 
@@ -61,7 +136,7 @@ impl Dump for Ty {
         match self {
             Ty::String => "str".into(),
             Ty::Int => "int".into(),
-            Ty::List => "list".into(),
+            Ty::List(_) => self.to_string(),
             Ty::Dir => "dir".into(),
             Ty::File => "file".into(),
             Ty::Unknown => "unknown".into(),
@@ -87,7 +162,9 @@ impl Dump for Expr {
         match self {
             Expr::Lit(l) => l.dump(),
             Expr::Ref(r) => r.dump(),
-            Expr::If(_) => todo!(),
+            Expr::If(i) => i.dump(),
+            Expr::For(f) => f.dump(),
+            Expr::List(l) => format!("[{}]", l.dump()),
         }
     }
 }
@@ -96,38 +173,94 @@ impl Dump for Lit {
     fn dump(&self) -> String {
         match self {
             Lit::String(s) => format!("\"{}\"", s),
+            Lit::Raw(s) => dump_raw(s),
             Lit::File(f) => {
-                let mut buf = format!("\"{}\":", f.path);
-                buf.push_str(&f.content.dump());
+                let mut buf = f.annots.dump();
+                buf.push_str(&format!("\"{}\": ", dump_path(&f.path)));
+                buf.push_str(&dump_body(&f.content));
                 buf
             }
+            Lit::Link(l) => format!(
+                "{}link \"{}\": \"{}\"",
+                l.annots.dump(),
+                dump_path(&l.path),
+                dump_path(&l.target)
+            ),
             Lit::Dir(d) => {
-                let mut buf = format!("\"{}\" {{", d.path.to_str().unwrap());
+                let mut buf = d.annots.dump();
+                buf.push_str(&format!("\"{}\" {{", dump_path(&d.path)));
                 for child in &d.children {
                     buf.push_str(&child.dump());
                     buf.push_str(", ");
                 }
+                buf.push('}');
                 buf
             }
             Lit::Int(i) => i.to_string(),
+            Lit::Bool(b) => b.to_string(),
+            Lit::List(l) => {
+                let items = l.iter().map(|i| i.dump()).collect::<Vec<String>>();
+                format!("[{}]", items.join(", "))
+            }
+            Lit::BinOp(op) if op.op == Op::Index => {
+                format!("{}[{}]", op.lhs.dump(), op.rhs.dump())
+            }
             Lit::BinOp(op) => format!("({} {} {})", op.lhs.dump(), op.op, op.rhs.dump()),
+            Lit::UnOp(op) => format!("({}{})", op.op, op.expr.dump()),
+            Lit::Pipe(pipe) => {
+                let args = pipe
+                    .args
+                    .iter()
+                    .map(|arg| arg.dump())
+                    .collect::<Vec<String>>();
+                match args.is_empty() {
+                    true => format!("({} | {})", pipe.val.dump(), pipe.filter),
+                    false => format!(
+                        "({} | {}({}))",
+                        pipe.val.dump(),
+                        pipe.filter,
+                        args.join(", ")
+                    ),
+                }
+            }
         }
     }
 }
 
+impl Dump for If {
+    fn dump(&self) -> String {
+        let mut buf = format!("@if {} {{{{{}}}}}", self.cond.dump(), self.then.dump());
+        if !self.els.is_empty() {
+            buf.push_str(&format!(" @else {{{{{}}}}}", self.els.dump()));
+        }
+        buf
+    }
+}
+
+impl Dump for For {
+    fn dump(&self) -> String {
+        format!(
+            "@for {} in {} {{{{{}}}}}",
+            self.var,
+            self.iter.dump(),
+            self.body.dump()
+        )
+    }
+}
+
 impl Dump for Ref {
     fn dump(&self) -> String {
         let mut buf = String::from("@");
         buf.push_str(&self.name);
-        if self.args.len() > 0 {
-            buf.push_str("(");
+        if !self.args.is_empty() {
+            buf.push('(');
             for (ix, arg) in self.args.iter().enumerate() {
                 if ix > 0 {
                     buf.push_str(", ");
                 }
                 buf.push_str(&arg.dump());
             }
-            buf.push_str(")");
+            buf.push(')');
         }
         buf
     }
@@ -135,6 +268,9 @@ impl Dump for Ref {
 
 impl Dump for &(String, Expr) {
     fn dump(&self) -> String {
+        if self.0.is_empty() {
+            return self.1.dump();
+        }
         format!("{}: {}", self.0, self.1.dump())
     }
 }
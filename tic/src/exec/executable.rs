@@ -1,23 +1,33 @@
 use std::io::Error;
-use std::io::Write;
+use std::path::Component;
 
+use crate::ast::Annot;
+use crate::ast::Ast;
+use crate::ast::Cfg;
 use crate::ast::Dir;
+use crate::ast::Eol;
 use crate::ast::File;
+use crate::ast::Link;
 use crate::ast::Lit;
 use crate::ast::LitExecutable;
 use crate::ast::Ty;
 use crate::exec::ExecErr;
 use crate::exec::Expr;
+use crate::exec::Scope;
 use crate::exec::Syms;
 
-use super::resolve::Resolve;
+use super::plan::{Entry, Plan};
+use super::resolve::{skipped, Resolve};
 
+/// Resolves a file, dir or link into the entries it adds to a `Plan`,
+/// nothing is written until the plan is applied.
 pub trait Executable: Resolve {
     fn execute(
         self,
         syms: &Syms,
         parent: std::path::PathBuf,
         args: Vec<(String, Expr)>,
+        plan: &mut Plan,
     ) -> Result<(), ExecErr>;
 }
 
@@ -27,12 +37,13 @@ impl Executable for LitExecutable {
         syms: &Syms,
         parent: std::path::PathBuf,
         args: Vec<(String, Expr)>,
+        plan: &mut Plan,
     ) -> Result<(), ExecErr> {
         use LitExecutable as Le;
         match self {
-            Le::File(file) => file.execute(syms, parent, args),
-            Le::Dir(dir) => dir.execute(syms, parent, args),
-            Le::String(st) => st.execute(syms, parent, args),
+            Le::File(file) => file.execute(syms, parent, args, plan),
+            Le::Dir(dir) => dir.execute(syms, parent, args, plan),
+            Le::String(st) => st.execute(syms, parent, args, plan),
         }
     }
 }
@@ -43,26 +54,111 @@ impl Executable for Dir {
         syms: &Syms,
         parent: std::path::PathBuf,
         args: Vec<(String, Expr)>,
+        plan: &mut Plan,
     ) -> Result<(), ExecErr> {
-        let path = parent.join(&self.path);
-        if !path.exists() {
-            std::fs::create_dir_all(&path)?;
+        if skipped(syms, &self.annots, args.clone())? {
+            return Ok(());
         }
+        let path = join(syms, parent, self.path, args.clone())?;
+        let mode = mode(syms, &self.annots, args.clone())?;
+        plan.push(Entry::Dir {
+            path: path.clone(),
+            mode,
+        })?;
 
-        for child in self.children.into_iter() {
-            match child {
-                Expr::Lit(Lit::File(file)) => file.execute(syms, path.clone(), args.clone())?,
-                Expr::Lit(Lit::Dir(dir)) => dir.execute(syms, path.clone(), args.clone())?,
-                Expr::Ref(ref_) => ref_
-                    .clone()
-                    .resolve::<LitExecutable>(syms, &Ty::Unknown, args.clone())?
-                    .execute(syms, path.clone(), args.clone())?,
-                _ => return Err(Error::new(std::io::ErrorKind::InvalidData, "Expected dir").into()),
-            }
+        let mut args = args;
+        if let Some(scope) = self.scope {
+            args.push(Scope::Local(scope).arg());
         }
+        execute_children(self.children, syms, path, args, plan)
+    }
+}
 
-        Ok(())
+/// Reads the `[mode=..]` annotation, an octal mode such as `755`,
+/// `0755` or `0o755`.
+fn mode(syms: &Syms, annots: &[Annot], args: Vec<(String, Expr)>) -> Result<Option<u32>, ExecErr> {
+    let mode = match Annot::get(annots, "mode") {
+        Some(mode) => mode.clone().resolve::<String>(syms, &Ty::String, args)?,
+        None => return Ok(None),
+    };
+    let digits = mode.strip_prefix("0o").unwrap_or(&mode);
+    match u32::from_str_radix(digits, 8) {
+        Ok(bits) if bits <= 0o7777 => Ok(Some(bits)),
+        _ => Err(ExecErr::InvalidArgument(format!(
+            "invalid mode `{}`, expected an octal mode such as 755",
+            mode
+        ))),
+    }
+}
+
+/// Resolves the name of a file or dir and joins it onto `parent`.
+/// Names cannot be absolute or contain `..`, so nothing is written
+/// outside of the output dir.
+fn join(
+    syms: &Syms,
+    parent: std::path::PathBuf,
+    path: Vec<Expr>,
+    args: Vec<(String, Expr)>,
+) -> Result<std::path::PathBuf, ExecErr> {
+    let name = path.resolve::<String>(syms, &Ty::String, args)?;
+    let escapes = std::path::Path::new(&name).components().any(|c| {
+        matches!(
+            c,
+            Component::ParentDir | Component::RootDir | Component::Prefix(_)
+        )
+    });
+    if escapes {
+        return Err(ExecErr::InvalidArgument(format!(
+            "`{}` points outside of the output dir",
+            name
+        )));
     }
+    Ok(parent.join(name))
+}
+
+/// Executes the children of a directory, expanding any `@if`
+/// and `@for` into the children they evaluate to.
+fn execute_children(
+    children: Vec<Expr>,
+    syms: &Syms,
+    path: std::path::PathBuf,
+    args: Vec<(String, Expr)>,
+    plan: &mut Plan,
+) -> Result<(), ExecErr> {
+    for child in children.into_iter() {
+        match child {
+            Expr::Lit(Lit::File(file)) => file.execute(syms, path.clone(), args.clone(), plan)?,
+            Expr::Lit(Lit::Dir(dir)) => dir.execute(syms, path.clone(), args.clone(), plan)?,
+            Expr::Lit(Lit::Link(link)) => link.execute(syms, path.clone(), args.clone(), plan)?,
+            Expr::Ref(ref_) => {
+                let exe =
+                    ref_.clone()
+                        .resolve::<LitExecutable>(syms, &Ty::Unknown, args.clone())?;
+                exe.execute(syms, path.clone(), ref_.bind(syms, args.clone())?, plan)?
+            }
+            Expr::If(if_) => {
+                let children = if if_.cond.resolve::<bool>(syms, &Ty::Unknown, args.clone())? {
+                    if_.then
+                } else {
+                    if_.els
+                };
+                execute_children(children, syms, path.clone(), args.clone(), plan)?
+            }
+            Expr::For(for_) => {
+                let items = for_
+                    .iter
+                    .resolve::<Vec<Lit>>(syms, &Ty::Unknown, args.clone())?;
+                for item in items.into_iter() {
+                    let mut args = args.clone();
+                    args.push((for_.var.clone(), Expr::Lit(item)));
+                    execute_children(for_.body.clone(), syms, path.clone(), args, plan)?;
+                }
+            }
+            _ => return Err(Error::new(std::io::ErrorKind::InvalidData, "Expected dir").into()),
+        }
+    }
+
+    Ok(())
 }
 
 impl Executable for File {
@@ -71,17 +167,181 @@ impl Executable for File {
         syms: &Syms,
         parent: std::path::PathBuf,
         args: Vec<(String, Expr)>,
+        plan: &mut Plan,
     ) -> Result<(), ExecErr> {
-        let path = parent.join(&self.path);
-        if !parent.exists() {
-            std::fs::create_dir_all(&parent)?;
+        if skipped(syms, &self.annots, args.clone())? {
+            return Ok(());
         }
+        let path = join(syms, parent, self.path, args.clone())?;
+        let encoding = match Annot::get(&self.annots, "encoding") {
+            Some(enc) => Some(
+                enc.clone()
+                    .resolve::<String>(syms, &Ty::String, args.clone())?,
+            ),
+            None => None,
+        };
+        let raw = verbatim(syms, &self.content, &args);
+        let body = self
+            .content
+            .resolve::<String>(syms, &Ty::String, args.clone())?;
+        let bytes = match encoding.as_deref() {
+            None | Some("utf8") if raw => body.into_bytes(),
+            None | Some("utf8") => render(syms.cfg(), body).into_bytes(),
+            Some("base64") => decode(&body)?,
+            Some(enc) => {
+                return Err(ExecErr::InvalidArgument(format!(
+                    "unknown encoding `{}`, expected utf8 or base64",
+                    enc
+                )))
+            }
+        };
+        let mode = mode(syms, &self.annots, args)?;
+        plan.push(Entry::File {
+            path,
+            body: bytes,
+            mode,
+        })
+    }
+}
 
-        let mut fi = std::fs::File::create(path)?;
-        let body = self.content.resolve::<String>(syms, &Ty::String, args)?;
-        let _ = fi.write_all(body.as_bytes());
-        Ok(())
+impl Executable for Link {
+    fn execute(
+        self,
+        syms: &Syms,
+        parent: std::path::PathBuf,
+        args: Vec<(String, Expr)>,
+        plan: &mut Plan,
+    ) -> Result<(), ExecErr> {
+        if skipped(syms, &self.annots, args.clone())? {
+            return Ok(());
+        }
+        let hard = match Annot::get(&self.annots, "hard") {
+            Some(hard) => hard
+                .clone()
+                .resolve::<bool>(syms, &Ty::Unknown, args.clone())?,
+            None => false,
+        };
+        let target = self
+            .target
+            .resolve::<String>(syms, &Ty::String, args.clone())?;
+        // The name may hold dirs as well, `a/b`, so the depth is
+        // counted from where the link ends up.
+        let path = join(syms, parent, self.path, args)?;
+        let depth = path
+            .parent()
+            .and_then(|dir| dir.strip_prefix(&plan.root).ok())
+            .map_or(0, |rel| {
+                rel.components()
+                    .filter(|c| matches!(c, Component::Normal(_)))
+                    .count()
+            });
+        if escapes(depth, std::path::Path::new(&target)) {
+            return Err(ExecErr::InvalidArgument(format!(
+                "link target `{}` points outside of the output dir",
+                target
+            )));
+        }
+
+        plan.push(Entry::Link { path, target, hard })
+    }
+}
+
+/// Whether `target`, relative to a dir `depth` levels below the
+/// output dir, points outside of it. Absolute targets always do.
+fn escapes(mut depth: usize, target: &std::path::Path) -> bool {
+    for comp in target.components() {
+        match comp {
+            Component::Normal(_) => depth += 1,
+            Component::CurDir => {}
+            Component::ParentDir if depth > 0 => depth -= 1,
+            _ => return true,
+        }
+    }
+    false
+}
+
+/// Decodes the body of an `[encoding=base64]` file, which is written
+/// byte for byte. Whitespace is ignored so the body can be wrapped.
+fn decode(body: &str) -> Result<Vec<u8>, ExecErr> {
+    use base64::Engine;
+    let body = body
+        .chars()
+        .filter(|ch| !ch.is_whitespace())
+        .collect::<String>();
+    base64::engine::general_purpose::STANDARD
+        .decode(body)
+        .map_err(|err| ExecErr::InvalidArgument(format!("invalid base64 body: {}", err)))
+}
+
+/// Whether the content is a raw body, written in place or through
+/// a `let` or param. Raw bodies are copied without applying the `cfg`.
+fn verbatim(syms: &Syms, content: &[Expr], args: &[(String, Expr)]) -> bool {
+    match content {
+        [Expr::Lit(Lit::Raw(_))] => true,
+        [Expr::Ref(r)] if r.args.is_empty() => {
+            match args.iter().rev().find(|(name, _)| name == &r.name) {
+                Some((_, expr)) => verbatim(syms, std::slice::from_ref(expr), &[]),
+                None => match syms.lookup(&r.name, args).map(|sym| &sym.val) {
+                    Some(Ast::Let(let_)) => verbatim(syms, &let_.expr, args),
+                    _ => false,
+                },
+            }
+        }
+        _ => false,
+    }
+}
+
+/// Applies the formatting options of the `cfg` block to a file body:
+/// each level of leading indentation becomes the indent unit, line
+/// endings are normalised and the final newline is added or removed.
+fn render(cfg: &Cfg, body: String) -> String {
+    if cfg.indent.is_none() && cfg.eol.is_none() && cfg.final_newline.is_none() {
+        return body;
+    }
+    let eol = match cfg.eol {
+        Some(Eol::Crlf) => "\r\n",
+        _ => "\n",
+    };
+    let had_newline = body.ends_with('\n');
+    let level = indent_level(&body);
+    let mut lines = body
+        .trim_end_matches(['\r', '\n'])
+        .split('\n')
+        .map(|line| {
+            let line = line.strip_suffix('\r').unwrap_or(line);
+            match (&cfg.indent, &level) {
+                (Some(unit), Some(level)) => {
+                    let mut rest = line;
+                    let mut depth = 0;
+                    while let Some(next) = rest.strip_prefix(level.as_str()) {
+                        rest = next;
+                        depth += 1;
+                    }
+                    unit.repeat(depth) + rest
+                }
+                _ => line.to_owned(),
+            }
+        })
+        .collect::<Vec<String>>()
+        .join(eol);
+    if cfg.final_newline.unwrap_or(had_newline) {
+        lines.push_str(eol);
+    }
+    lines
+}
+
+/// One level of indentation in `body`: a tab if any line is indented
+/// with tabs, otherwise the shortest run of leading spaces.
+fn indent_level(body: &str) -> Option<String> {
+    let lines = body.lines().filter(|line| !line.trim().is_empty());
+    if lines.clone().any(|line| line.starts_with('\t')) {
+        return Some("\t".into());
     }
+    lines
+        .map(|line| line.len() - line.trim_start_matches(' ').len())
+        .filter(|&n| n > 0)
+        .min()
+        .map(|n| " ".repeat(n))
 }
 
 impl Executable for String {
@@ -90,8 +350,278 @@ impl Executable for String {
         _: &Syms,
         _: std::path::PathBuf,
         _: Vec<(String, Expr)>,
+        _: &mut Plan,
     ) -> Result<(), ExecErr> {
         println!("{}", self);
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Expr, Lit};
+    use crate::exec::{fixture::Fixture, ExecErr};
+
+    #[test]
+    fn test_dir_control_flow() {
+        let src = r#"
+            let main_rs(name: str) = {{fn {$name$}();}};
+
+            main dir out {
+                @if with_ci { ".github" { "ci.yml": {{on: push}} } } @else { "no-ci" }
+                @for svc in services {
+                    @if svc == "api" { api { "main.rs": @main_rs(name: svc) } }
+                    @else { worker }
+                }
+                "README.md"
+                [skip_if=with_ci] "Jenkinsfile"
+            }
+        "#;
+        let services = Lit::List(vec![
+            Lit::String("api".into()),
+            Lit::String("worker".into()),
+        ]);
+        let args = vec![
+            ("with_ci".to_string(), Expr::Lit(Lit::String("true".into()))),
+            ("services".to_string(), Expr::Lit(services)),
+        ];
+        let fx = Fixture::with_args(src, args);
+        fx.run().expect("Failed to execute");
+
+        let out = fx.dir.join("out");
+        assert_eq!(fx.read("out/.github/ci.yml"), "on: push");
+        assert!(!out.join("no-ci").exists());
+        assert_eq!(fx.read("out/api/main.rs"), "fn api();");
+        assert!(out.join("worker").is_dir());
+        assert!(out.join("README.md").is_file());
+        assert!(!out.join("Jenkinsfile").exists());
+    }
+
+    #[test]
+    fn test_interpolated_names() {
+        let src = r#"
+            req name: str;
+            req module: str;
+
+            main dir project: !name {
+                src/{$ module $} { "{$ module $}.go": {{package {$ module $}}} },
+                "{$ name $}.md"
+            }
+        "#;
+        let args = vec![
+            ("name".to_string(), Expr::Lit(Lit::String("demo".into()))),
+            ("module".to_string(), Expr::Lit(Lit::String("core".into()))),
+        ];
+        let fx = Fixture::with_args(src, args);
+        fx.run().expect("Failed to execute");
+
+        assert_eq!(fx.read("demo/src/core/core.go"), "package core");
+        assert!(fx.dir.join("demo/demo.md").is_file());
+
+        // Names cannot leave the output dir.
+        let args = vec![("name".to_string(), Expr::Lit(Lit::String("../x".into())))];
+        let fx = Fixture::with_args(r#"req name: str; main dir out { "{$ name $}.txt" }"#, args);
+        assert!(matches!(fx.run(), Err(ExecErr::InvalidArgument(_))));
+        let fx = Fixture::new(r#"main dir out { "/tmp/evil.txt": "x" }"#);
+        assert!(matches!(fx.run(), Err(ExecErr::InvalidArgument(_))));
+    }
+
+    #[test]
+    fn test_cfg() {
+        let fx = Fixture::new(
+            "
+            cfg { [indent=2s] [eol=crlf] [final_newline=true] [on_conflict=skip] }
+            main dir out { \"a.txt\": {{x\n\ty}}, \"b.txt\": {{new}} }
+        ",
+        );
+        std::fs::create_dir_all(fx.dir.join("out")).unwrap();
+        std::fs::write(fx.dir.join("out/b.txt"), "old").unwrap();
+        fx.run().expect("Failed to execute");
+
+        assert_eq!(fx.read("out/a.txt"), "x\r\n  y\r\n");
+        assert_eq!(fx.read("out/b.txt"), "old");
+
+        // Dedented bodies are indented with spaces, each level is replaced.
+        let fx = Fixture::new(
+            "
+            cfg { [indent=tab] }
+            main dir out { \"a.py\": {{
+                def f():
+                    if x:
+                        return 1
+            }} }
+        ",
+        );
+        fx.run().expect("Failed to execute");
+        assert_eq!(fx.read("out/a.py"), "def f():\n\tif x:\n\t\treturn 1");
+    }
+
+    #[test]
+    fn test_binary_and_verbatim() {
+        let fx = Fixture::new(
+            r###"
+            main dir out {
+                [encoding=base64] "a.bin": r#{{
+                    AP+AAQ==
+                }}#,
+                "b.txt": r#{{  {$ x $} }}#,
+            }
+        "###,
+        );
+        fx.run().expect("Failed to execute");
+
+        assert_eq!(
+            std::fs::read(fx.dir.join("out/a.bin")).unwrap(),
+            [0, 255, 128, 1]
+        );
+        assert_eq!(fx.read("out/b.txt"), "  {$ x $} ");
+
+        // The cfg does not apply to raw bodies, a Makefile keeps its tabs.
+        let fx = Fixture::new(
+            "
+            cfg { [indent=4s] [eol=crlf] [final_newline=false] }
+            let make = r#{{all:\n\techo hi\n}}#;
+            main dir out { \"Makefile\": r#{{all:\n\techo hi\n}}#, \"b.mk\": make }
+        ",
+        );
+        fx.run().expect("Failed to execute");
+        assert_eq!(fx.read("out/Makefile"), "all:\n\techo hi\n");
+        assert_eq!(fx.read("out/b.mk"), "all:\n\techo hi\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_mode() {
+        use std::os::unix::fs::PermissionsExt;
+        let fx = Fixture::new(
+            r#"
+            main dir out {
+                [mode=0o755] "run.sh": {{#!/bin/sh}},
+                [mode=700] "private" { "key": "" },
+            }
+        "#,
+        );
+        fx.run().expect("Failed to execute");
+
+        let mode = |path: &str| {
+            let meta = std::fs::metadata(fx.dir.join("out").join(path)).unwrap();
+            meta.permissions().mode() & 0o777
+        };
+        assert_eq!(mode("run.sh"), 0o755);
+        assert_eq!(mode("private"), 0o700);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_link() {
+        let fx = Fixture::new(
+            r#"
+            main dir out {
+                "rustfmt.toml": "max_width = 80",
+                "core" { link "rustfmt.toml": "../rustfmt.toml" },
+                link { "a.txt" },
+            }
+        "#,
+        );
+        fx.run().expect("Failed to execute");
+        let out = fx.dir.join("out");
+        assert_eq!(
+            std::fs::read_link(out.join("core/rustfmt.toml")).unwrap(),
+            std::path::PathBuf::from("../rustfmt.toml")
+        );
+        assert!(out.join("link/a.txt").is_file());
+
+        let fx = Fixture::new(r#"main dir out { link "passwd": "../../etc/passwd" }"#);
+        assert!(fx.run().is_err());
+        let fx = Fixture::new(r#"main dir out { link "passwd": "/etc/passwd" }"#);
+        assert!(fx.run().is_err());
+        let fx = Fixture::new(r#"main dir out { sub { link "../l2": "../../x" } }"#);
+        assert!(fx.run().is_err());
+        // Counted from the dir the link is in, not the one declaring it.
+        let fx = Fixture::new(r#"main dir out { sub { link "d/l": "../../a.txt" } }"#);
+        assert!(fx.plan().is_ok());
+        let fx = Fixture::new(r#"main dir out { sub { link "d/l": "../../../../x" } }"#);
+        assert!(fx.plan().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_hard_link() {
+        use std::os::unix::fs::MetadataExt;
+        let fx = Fixture::new(
+            r#"
+            main dir out {
+                "a.txt": "x",
+                sub { [hard=true] link "b.txt": "../a.txt" },
+            }
+        "#,
+        );
+        fx.run().expect("Failed to execute");
+        let ino = |path: &str| std::fs::metadata(fx.dir.join(path)).unwrap().ino();
+        assert_eq!(ino("out/a.txt"), ino("out/sub/b.txt"));
+        assert_eq!(fx.read("out/sub/b.txt"), "x");
+    }
+
+    #[test]
+    fn test_local_lets() {
+        let fx = Fixture::new(
+            r#"
+            let readme = {{global}};
+            let other = {{{$ readme $}}};
+
+            main dir out {
+                let readme = {{root}};
+                "README.md": @readme,
+                "OTHER.md": @other,
+                a {
+                    let readme = {{a {$ helper $}}};
+                    let helper = {{h}};
+                    "README.md": @readme
+                }
+                b { "README.md": @readme }
+            }
+        "#,
+        );
+        fx.run().expect("Failed to execute");
+
+        assert_eq!(fx.read("out/README.md"), "root");
+        assert_eq!(fx.read("out/OTHER.md"), "global");
+        assert_eq!(fx.read("out/a/README.md"), "a h");
+        assert_eq!(fx.read("out/b/README.md"), "root");
+
+        let mut syms = crate::exec::Syms::new(Vec::new());
+        let mut syn = crate::syntax::Syntax::new(b"dir x { let a = \"1\"; let a = \"2\"; }");
+        assert!(syms.add_all_ast(syn.parse_all().unwrap()).is_err());
+    }
+
+    #[test]
+    fn test_components() {
+        let src = r#"
+            dir member(name: str, kind: str = "lib"): crates/{$ name $} {
+                "Cargo.toml": {{name = "{$ name $}"}},
+                src { @if kind == "lib" { "lib.rs" } @else { "main.rs" } }
+            }
+            file notes(name: str): "{$ name $}.md" {{# {$ name | upper $}}};
+
+            main dir ws {
+                @member(name: "core"),
+                @member("cli", kind: "bin"),
+                @for m in members { @member(m) @notes(m) }
+            }
+        "#;
+        let args = vec![(
+            "members".to_string(),
+            Expr::Lit(Lit::List(vec![Lit::String("extra".into())])),
+        )];
+        let fx = Fixture::with_args(src, args);
+        fx.run().expect("Failed to execute");
+
+        let out = fx.dir.join("ws");
+        assert_eq!(fx.read("ws/crates/core/Cargo.toml"), "name = \"core\"");
+        assert_eq!(fx.read("ws/crates/cli/Cargo.toml"), "name = \"cli\"");
+        assert_eq!(fx.read("ws/crates/extra/Cargo.toml"), "name = \"extra\"");
+        assert!(out.join("crates/core/src/lib.rs").is_file());
+        assert!(out.join("crates/cli/src/main.rs").is_file());
+        assert_eq!(fx.read("ws/extra.md"), "# EXTRA");
+    }
+}
@@ -0,0 +1,74 @@
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use crate::ast::{Ast, Expr};
+use crate::err::ExecErr;
+use crate::syntax::Syntax;
+
+use super::plan::{Plan, Resolved};
+use super::{Exec, Syms};
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+/// A parsed template and an empty dir of its own to generate it
+/// into, which is removed again on drop.
+pub struct Fixture {
+    pub syms: Syms,
+    pub dir: PathBuf,
+}
+
+impl Fixture {
+    pub fn new(src: &str) -> Fixture {
+        Fixture::with_args(src, Vec::new())
+    }
+
+    pub fn with_args(src: &str, args: Vec<(String, Expr)>) -> Fixture {
+        let mut syms = Syms::new(args);
+        let mut syn = Syntax::new(src.as_bytes());
+        syms.add_all_ast(syn.parse_all().expect("Failed to parse"))
+            .expect("Failed to add symbols");
+        Fixture {
+            syms,
+            dir: temp_dir(),
+        }
+    }
+
+    pub fn main(&self) -> Ast {
+        self.syms.main().expect("Expected main")
+    }
+
+    pub fn plan(&self) -> Result<Plan, ExecErr> {
+        Exec::plan(
+            &self.syms,
+            self.main(),
+            self.syms.args.clone(),
+            self.dir.clone(),
+        )
+    }
+
+    pub fn run(&self) -> Result<Vec<Resolved>, ExecErr> {
+        self.plan()?.apply()
+    }
+
+    pub fn read(&self, path: &str) -> String {
+        std::fs::read_to_string(self.dir.join(path)).expect("Failed to read")
+    }
+}
+
+impl Drop for Fixture {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// An empty dir no other test, or test run, uses.
+pub fn temp_dir() -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "tipis-test-{}-{}",
+        std::process::id(),
+        COUNT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("Failed to create test dir");
+    dir
+}
@@ -1,25 +1,51 @@
-use std::{collections::HashMap, io::Error};
+use std::{collections::HashMap, io::Error, path::PathBuf};
 
 use crate::{
-    ast::{Ast, AstKind, Expr, Ty},
+    ast::{Ast, AstKind, Cfg, Dir, Expr, Lit, LitValue, Param, Req, Ty},
     err::ExecErr,
 };
 
+use self::dump::Dump;
 use self::executable::Executable;
+use self::plan::{Plan, Resolved};
+use self::resolve::Resolve;
 
+pub mod builtin;
 pub mod dump;
 pub mod executable;
+#[cfg(test)]
+pub(crate) mod fixture;
+pub mod plan;
 pub mod resolve;
 
 pub struct Exec;
 
 impl Exec {
-    pub fn run(syms: &Syms, ast: Ast, args: Vec<(String, Expr)>) -> Result<(), ExecErr> {
+    /// Generates `ast` into the `out` dir, which is created if it is
+    /// missing. `Syms::out` is the one the template asks for.
+    pub fn run(
+        syms: &Syms,
+        ast: Ast,
+        args: Vec<(String, Expr)>,
+        out: PathBuf,
+    ) -> Result<Vec<Resolved>, ExecErr> {
+        Self::plan(syms, ast, args, out)?.apply()
+    }
+
+    /// Resolves the whole tree without touching the disk.
+    pub fn plan(
+        syms: &Syms,
+        ast: Ast,
+        args: Vec<(String, Expr)>,
+        out: PathBuf,
+    ) -> Result<Plan, ExecErr> {
+        let mut plan = Plan::new(syms, out.clone());
         match ast {
-            Ast::File(file) => file.execute(syms, std::path::PathBuf::new(), args),
-            Ast::Dir(dir) => dir.execute(syms, "./".into(), args),
+            Ast::File(file) => file.execute(syms, out, args, &mut plan)?,
+            Ast::Dir(dir) => dir.execute(syms, out, args, &mut plan)?,
             _ => return Err(Error::new(std::io::ErrorKind::InvalidData, "Expected dir").into()),
         }
+        Ok(plan)
     }
 }
 
@@ -28,18 +54,52 @@ pub struct Syms {
     pub args: Vec<(String, Expr)>,
     pub symbols: HashMap<Key, Sym>,
     pub main: Option<Sym>,
+    pub cfg: Option<Cfg>,
+    /// Number of local scopes handed out so far.
+    scopes: usize,
 }
 
+/// Name of the arg holding the current scope, it cannot
+/// clash with a symbol as it is not a valid identifier.
+pub const SCOPE: &str = "@scope";
+
+static NO_CFG: std::sync::LazyLock<Cfg> = std::sync::LazyLock::new(Cfg::default);
+
 impl Syms {
     pub fn new(args: Vec<(String, Expr)>) -> Syms {
         Syms {
             args,
             symbols: HashMap::new(),
             main: None,
+            cfg: None,
+            scopes: 0,
         }
     }
 
-    pub fn add(&mut self, sym: Sym) -> Result<(), ExecErr> {
+    pub fn cfg(&self) -> &Cfg {
+        self.cfg.as_ref().unwrap_or(&NO_CFG)
+    }
+
+    /// The dir the template is generated into unless told otherwise.
+    pub fn out(&self) -> PathBuf {
+        self.cfg().out.clone().unwrap_or_else(|| "./".into())
+    }
+
+    pub fn add(&mut self, mut sym: Sym) -> Result<(), ExecErr> {
+        if let Ast::Use(ref u) = sym.val {
+            // Imports are expanded before the symbols are added.
+            return Err(ExecErr::NotFound(format!("unresolved import `{}`", u.ns)));
+        }
+        if let Ast::Cfg(cfg) = sym.val {
+            if self.cfg.is_some() {
+                return Err(ExecErr::AlreadyExists("cfg".into()));
+            }
+            self.cfg = Some(cfg);
+            return Ok(());
+        }
+        if let Ast::Dir(ref mut dir) = sym.val {
+            self.add_scope(dir, "")?;
+        }
         let key = Key(sym.name(), sym.scope.to_owned());
         if sym.main() {
             if self.main.is_some() {
@@ -55,6 +115,41 @@ impl Syms {
         Ok(())
     }
 
+    /// Gives every dir declaring `let`s its own scope and adds the
+    /// `let`s to it. Scopes are named by their path from the globals,
+    /// e.g. `/1/3`, so the enclosing scope is found by dropping the
+    /// last segment.
+    fn add_scope(&mut self, dir: &mut Dir, parent: &str) -> Result<(), ExecErr> {
+        if !dir.lets.is_empty() {
+            self.scopes += 1;
+            let scope = format!("{}/{}", parent, self.scopes);
+            for let_ in dir.lets.iter() {
+                self.add(Sym {
+                    scope: Scope::Local(scope.clone()),
+                    val: Ast::Let(let_.clone()),
+                })?;
+            }
+            dir.scope = Some(scope);
+        }
+        let scope = dir.scope.clone().unwrap_or_else(|| parent.to_owned());
+        self.add_child_scopes(&mut dir.children, &scope)
+    }
+
+    fn add_child_scopes(&mut self, children: &mut [Expr], parent: &str) -> Result<(), ExecErr> {
+        for child in children.iter_mut() {
+            match child {
+                Expr::Lit(Lit::Dir(dir)) => self.add_scope(dir, parent)?,
+                Expr::If(if_) => {
+                    self.add_child_scopes(&mut if_.then, parent)?;
+                    self.add_child_scopes(&mut if_.els, parent)?;
+                }
+                Expr::For(for_) => self.add_child_scopes(&mut for_.body, parent)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
     pub fn add_ast(&mut self, ast: Ast) -> Result<(), ExecErr> {
         self.add(Sym {
             scope: Scope::Global,
@@ -74,7 +169,69 @@ impl Syms {
     }
 
     pub fn has(&self, key: &Key) -> bool {
-        self.symbols.get(key).is_some()
+        self.symbols.contains_key(key)
+    }
+
+    /// Finds the symbol `name` refers to, starting in the current
+    /// scope of `args` and walking outward to the globals.
+    pub fn lookup(&self, name: &str, args: &[(String, Expr)]) -> Option<&Sym> {
+        let mut scope = match args.iter().rev().find(|(n, _)| n == SCOPE) {
+            Some((_, Expr::Lit(Lit::String(scope)))) => scope.as_str(),
+            _ => "",
+        };
+        while let Some(ix) = scope.rfind('/') {
+            let key = Key(name.to_owned(), Scope::Local(scope.to_owned()));
+            if let Some(sym) = self.symbols.get(&key) {
+                return Some(sym);
+            }
+            scope = &scope[..ix];
+        }
+        self.get(&Key(name.to_owned(), Scope::Global))
+    }
+
+    /// Checks the args against the `req` declarations, converting
+    /// them to the declared types. All problems are reported at once.
+    pub fn check_args(&mut self) -> Result<(), ExecErr> {
+        let mut reqs = self
+            .symbols
+            .values()
+            .filter_map(|sym| match sym.val {
+                Ast::Req(ref req) => Some(req),
+                _ => None,
+            })
+            .collect::<Vec<&Req>>();
+        reqs.sort_by(|a, b| a.name.cmp(&b.name));
+
+        let mut problems = Vec::new();
+        for (name, _) in self.args.iter() {
+            if !reqs.iter().any(|req| &req.name == name) {
+                problems.push(format!("unknown arg `{}`", name));
+            }
+        }
+
+        let mut args = Vec::with_capacity(reqs.len());
+        for req in reqs {
+            match self.args.iter().find(|(name, _)| name == &req.name) {
+                Some((_, Expr::Lit(lit))) => match coerce(lit.clone(), &req.ty) {
+                    Ok(lit) => args.push((req.name.clone(), Expr::Lit(lit))),
+                    Err(got) => {
+                        problems.push(format!("`{}` expects {}, got {}", req.name, req.ty, got))
+                    }
+                },
+                Some((_, expr)) => args.push((req.name.clone(), expr.clone())),
+                None => match req.default(self) {
+                    Some(Ok(lit)) => args.push((req.name.clone(), Expr::Lit(lit))),
+                    Some(Err(err)) => problems.push(format!("`{}`: {}", req.name, err)),
+                    None => problems.push(format!("missing `{}: {}`", req.name, req.ty)),
+                },
+            }
+        }
+
+        if !problems.is_empty() {
+            return Err(ExecErr::InvalidArgs(problems));
+        }
+        self.args = args;
+        Ok(())
     }
 
     pub fn main(&self) -> Option<Ast> {
@@ -82,6 +239,51 @@ impl Syms {
     }
 }
 
+impl Req {
+    /// The value used when no arg is given, either the declared
+    /// default or the empty value of an optional requirement.
+    pub fn default(&self, syms: &Syms) -> Option<Result<Lit, ExecErr>> {
+        match self.default {
+            Some(ref expr) => Some(
+                expr.clone()
+                    .resolve(syms, &self.ty, Vec::new())
+                    .map(|LitValue(lit)| lit),
+            ),
+            None if self.optional => Some(Ok(self.ty.empty())),
+            None => None,
+        }
+    }
+}
+
+/// Converts a command line value to `ty`, on failure
+/// returns the offending value for the error message.
+fn coerce(lit: Lit, ty: &Ty) -> Result<Lit, String> {
+    match (ty, lit) {
+        (Ty::Int, Lit::String(s)) => match s.trim().parse::<i64>() {
+            Ok(i) => Ok(Lit::Int(i.to_string())),
+            Err(_) => Err(format!("\"{}\"", s)),
+        },
+        (Ty::List(elem), lit) => {
+            let items = match lit {
+                // `a,b,c` on the command line is a list of three.
+                Lit::String(s) if s.is_empty() => Vec::new(),
+                Lit::String(s) => s
+                    .split(',')
+                    .map(|item| Lit::String(item.trim().to_owned()))
+                    .collect(),
+                lit => Vec::<Lit>::try_from(lit.clone()).map_err(|_| lit.dump())?,
+            };
+            let items = items
+                .into_iter()
+                .map(|item| coerce(item, elem))
+                .collect::<Result<Vec<Lit>, String>>()?;
+            Ok(Lit::List(items))
+        }
+        (Ty::String | Ty::Int, lit @ Lit::List(_)) => Err(lit.dump()),
+        (_, lit) => Ok(lit),
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Debug)]
 pub struct Key(pub String, pub Scope);
 
@@ -100,6 +302,7 @@ impl Sym {
             Ast::Req(_) => false,
             Ast::Ref(_) => false,
             Ast::Lit(_) => false,
+            Ast::Use(_) | Ast::Cfg(_) => false,
         }
     }
 
@@ -119,26 +322,28 @@ impl Sym {
             Ast::Dir(ref d) => d.alias.clone(),
             Ast::File(ref f) => f.alias.clone(),
             Ast::Ref(ref r) => r.name.clone(),
+            Ast::Use(ref u) => u.ns.clone(),
+            Ast::Cfg(_) => "cfg".into(),
             _ => unreachable!("A Literal cannot be inserted as a symbol."),
         }
     }
 
-    pub fn params(&self) -> Vec<(String, Ty)> {
+    pub fn params(&self) -> Vec<Param> {
         match self.val {
             Ast::Let(ref l) => l.params.clone(),
             Ast::Dir(ref d) => d.params.clone(),
             Ast::File(ref f) => f.params.clone(),
-            Ast::Req(_) | Ast::Ref(_) | Ast::Lit(_) => Vec::new(),
+            Ast::Req(_) | Ast::Ref(_) | Ast::Lit(_) | Ast::Use(_) | Ast::Cfg(_) => Vec::new(),
         }
     }
 
     pub fn ty(&self) -> Ty {
         match self.val {
-            Ast::Let(ref l) => l.ty,
-            Ast::Req(ref r) => r.ty,
+            Ast::Let(ref l) => l.ty.clone(),
+            Ast::Req(ref r) => r.ty.clone(),
             Ast::Dir(_) => Ty::Dir,
             Ast::File(_) => Ty::File,
-            Ast::Ref(_) => Ty::Unknown,
+            Ast::Ref(_) | Ast::Use(_) | Ast::Cfg(_) => Ty::Unknown,
             Ast::Lit(_) => Ty::String,
         }
     }
@@ -151,6 +356,8 @@ impl Sym {
             Ast::File(_) => AstKind::File,
             Ast::Ref(_) => AstKind::Req,
             Ast::Lit(_) => AstKind::Lit,
+            Ast::Use(_) => AstKind::Use,
+            Ast::Cfg(_) => AstKind::Cfg,
         }
     }
 }
@@ -160,3 +367,66 @@ pub enum Scope {
     Global,
     Local(String),
 }
+
+impl Scope {
+    /// The arg that makes this the current scope.
+    pub fn arg(&self) -> (String, Expr) {
+        let scope = match self {
+            Scope::Global => String::new(),
+            Scope::Local(scope) => scope.clone(),
+        };
+        (SCOPE.to_owned(), Expr::Lit(Lit::String(scope)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Expr, Lit};
+    use crate::err::ExecErr;
+    use crate::exec::Syms;
+    use crate::syntax::Syntax;
+
+    fn check(args: &[&str]) -> Result<Vec<(String, Expr)>, ExecErr> {
+        let src = "req name: str; req port: int; req tags: list<str>; req debug: int = 0; req license: str?;";
+        let args = crate::args(args.iter().map(|a| a.to_string()).collect()).unwrap();
+        let mut syms = Syms::new(args);
+        let mut syn = Syntax::new(src.as_bytes());
+        syms.add_all_ast(syn.parse_all().expect("Failed to parse"))
+            .expect("Failed to add symbols");
+        syms.check_args().map(|_| syms.args)
+    }
+
+    #[test]
+    fn test_check_args() {
+        let args = check(&["name=foo", "port=8080", "tags=a,b"]).expect("Valid args");
+        assert_eq!(
+            args,
+            vec![
+                ("debug".to_string(), Expr::Lit(Lit::Int("0".into()))),
+                ("license".to_string(), Expr::Lit(Lit::String("".into()))),
+                ("name".to_string(), Expr::Lit(Lit::String("foo".into()))),
+                ("port".to_string(), Expr::Lit(Lit::Int("8080".into()))),
+                (
+                    "tags".to_string(),
+                    Expr::Lit(Lit::List(vec![
+                        Lit::String("a".into()),
+                        Lit::String("b".into())
+                    ]))
+                ),
+            ]
+        );
+
+        match check(&["port=http", "colour=red"]) {
+            Err(ExecErr::InvalidArgs(problems)) => assert_eq!(
+                problems,
+                vec![
+                    "unknown arg `colour`",
+                    "missing `name: str`",
+                    "`port` expects int, got \"http\"",
+                    "missing `tags: list<str>`",
+                ]
+            ),
+            res => panic!("Expected invalid args, got {:?}", res),
+        }
+    }
+}
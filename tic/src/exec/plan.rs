@@ -0,0 +1,566 @@
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::ast::OnConflict;
+use crate::err::ExecErr;
+
+use super::Syms;
+
+/// Everything generating a template does to the disk. It is built
+/// before anything is written, so it can be shown or applied at once.
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// The dir the entries are generated into.
+    pub root: PathBuf,
+    pub on_conflict: OnConflict,
+    pub entries: Vec<Entry>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Entry {
+    Dir {
+        path: PathBuf,
+        mode: Option<u32>,
+    },
+    File {
+        path: PathBuf,
+        body: Vec<u8>,
+        mode: Option<u32>,
+    },
+    /// `target` is relative to the dir holding the link.
+    Link {
+        path: PathBuf,
+        target: String,
+        hard: bool,
+    },
+}
+
+impl Entry {
+    pub fn path(&self) -> &Path {
+        match self {
+            Entry::Dir { path, .. } | Entry::File { path, .. } | Entry::Link { path, .. } => path,
+        }
+    }
+
+    /// Whether applying the entry replaces something on the disk,
+    /// existing dirs are merged into instead. Links count as well,
+    /// even when they point nowhere.
+    pub fn conflicts(&self) -> bool {
+        match self {
+            Entry::Dir { path, .. } => path.symlink_metadata().is_ok_and(|meta| !meta.is_dir()),
+            Entry::File { path, .. } | Entry::Link { path, .. } => path.symlink_metadata().is_ok(),
+        }
+    }
+}
+
+/// What was done with a path that already existed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Resolved {
+    Skipped(PathBuf),
+    Overwritten(PathBuf),
+    BackedUp(PathBuf, PathBuf),
+}
+
+impl std::fmt::Display for Resolved {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Resolved::Skipped(path) => write!(f, "Skipped {}", path.display()),
+            Resolved::Overwritten(path) => write!(f, "Overwrote {}", path.display()),
+            Resolved::BackedUp(path, bak) => {
+                write!(f, "Moved {} to {}", path.display(), bak.display())
+            }
+        }
+    }
+}
+
+impl Plan {
+    /// An empty plan generating into `root`.
+    pub fn new(syms: &Syms, root: PathBuf) -> Plan {
+        Plan {
+            root,
+            on_conflict: syms.cfg().on_conflict.unwrap_or_default(),
+            entries: Vec::new(),
+        }
+    }
+
+    /// Adds an entry, a path can only be generated once. The same
+    /// dir may be listed again and is merged.
+    pub fn push(&mut self, entry: Entry) -> Result<(), ExecErr> {
+        match self.entries.iter().find(|e| e.path() == entry.path()) {
+            Some(prev) if *prev == entry && matches!(entry, Entry::Dir { .. }) => Ok(()),
+            Some(_) => Err(ExecErr::AlreadyExists(entry.path().display().to_string())),
+            None => {
+                self.entries.push(entry);
+                Ok(())
+            }
+        }
+    }
+
+    /// The paths the plan would replace.
+    pub fn conflicts(&self) -> Vec<&Path> {
+        self.entries
+            .iter()
+            .filter(|entry| entry.conflicts())
+            .map(Entry::path)
+            .collect()
+    }
+
+    /// Writes the plan to the disk and reports what was done with
+    /// the paths that already existed. With `fail`, nothing is
+    /// written if there are any.
+    ///
+    /// Everything is first written to a stage inside the output dir
+    /// and only moved into place once that worked. If moving
+    /// fails half-way, the changes made so far are undone.
+    pub fn apply(self) -> Result<Vec<Resolved>, ExecErr> {
+        let conflicts = self.conflicts();
+        if self.on_conflict == OnConflict::Fail && !conflicts.is_empty() {
+            return Err(ExecErr::Conflicts(
+                conflicts.iter().map(|p| p.display().to_string()).collect(),
+            ));
+        }
+
+        // Decided up front, so a prompt never sees a half written tree.
+        let mut actions = Vec::with_capacity(self.entries.len());
+        let mut skipped = Vec::new();
+        for entry in self.entries.iter() {
+            let action = match entry.conflicts() {
+                // Nothing can be generated below a dir that was skipped.
+                _ if skipped.iter().any(|dir| entry.path().starts_with(dir)) => {
+                    Some(OnConflict::Skip)
+                }
+                true if self.on_conflict == OnConflict::Prompt => Some(prompt(entry.path())?),
+                true => Some(self.on_conflict),
+                false => None,
+            };
+            if let (Entry::Dir { path, .. }, Some(OnConflict::Skip)) = (entry, action) {
+                skipped.push(path);
+            }
+            actions.push(action);
+        }
+
+        // The missing dirs of the root are only kept if the run works.
+        let mut created = Vec::new();
+        let res = create_dirs(&self.root, &mut created).and_then(|_| self.write(&actions));
+        if res.is_err() {
+            for change in created.into_iter().rev() {
+                change.undo();
+            }
+        }
+        res
+    }
+
+    fn write(&self, actions: &[Option<OnConflict>]) -> Result<Vec<Resolved>, ExecErr> {
+        let stage = self.stage_dir();
+        let mut changes = Vec::new();
+        let res = self
+            .stage(&stage, actions)
+            .and_then(|_| self.commit(&stage, actions, &mut changes));
+        if res.is_err() {
+            for change in changes.into_iter().rev() {
+                change.undo();
+            }
+        }
+        let _ = std::fs::remove_dir_all(&stage);
+        res
+    }
+
+    /// A hidden dir inside the root, writable wherever the output is
+    /// and on the same filesystem so staged entries can be moved
+    /// into place.
+    fn stage_dir(&self) -> PathBuf {
+        self.root
+            .join(format!(".tipis-stage-{}", std::process::id()))
+    }
+
+    /// Writes the files and links into `stage`, laid out like the
+    /// output dir. Dirs are only created where they are needed.
+    fn stage(&self, stage: &Path, actions: &[Option<OnConflict>]) -> Result<(), ExecErr> {
+        for (entry, action) in self.entries.iter().zip(actions.iter()) {
+            if *action == Some(OnConflict::Skip) {
+                continue;
+            }
+            let path = self.staged(stage, entry.path())?;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            match entry {
+                Entry::Dir { .. } => std::fs::create_dir_all(&path)?,
+                Entry::File { body, mode, .. } => {
+                    let mut fi = std::fs::File::create(&path)?;
+                    fi.write_all(body)?;
+                    set_mode(&path, *mode)?;
+                }
+                Entry::Link {
+                    target,
+                    hard: false,
+                    ..
+                } => symlink(target, &path)?,
+                Entry::Link {
+                    path: link,
+                    target,
+                    hard: true,
+                } => {
+                    // Files of this run are linked in the stage, so
+                    // the link keeps pointing at them once moved.
+                    let target = link.parent().unwrap_or(&self.root).join(target);
+                    let staged = self.staged(stage, &target)?;
+                    let source = if staged.is_file() { staged } else { target };
+                    std::fs::hard_link(&source, &path)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Moves the staged entries into place, recording every change.
+    /// Modes of dirs are set last, a read-only dir could not be
+    /// filled otherwise.
+    fn commit(
+        &self,
+        stage: &Path,
+        actions: &[Option<OnConflict>],
+        changes: &mut Vec<Change>,
+    ) -> Result<Vec<Resolved>, ExecErr> {
+        let old = stage.join(".old");
+        let mut report = Vec::new();
+        let mut modes = Vec::new();
+        for (entry, action) in self.entries.iter().zip(actions.iter()) {
+            let path = entry.path().to_path_buf();
+            match (entry, action) {
+                (_, Some(OnConflict::Skip)) => {
+                    report.push(Resolved::Skipped(path));
+                    continue;
+                }
+                (_, Some(OnConflict::Backup)) => {
+                    let bak = backup(&path);
+                    std::fs::rename(&path, &bak)?;
+                    changes.push(Change::Moved(path.clone(), bak.clone()));
+                    report.push(Resolved::BackedUp(path.clone(), bak));
+                }
+                (_, Some(_)) => {
+                    // Kept until the run is done, in case it is undone.
+                    std::fs::create_dir_all(&old)?;
+                    let prev = old.join(changes.len().to_string());
+                    std::fs::rename(&path, &prev)?;
+                    changes.push(Change::Moved(path.clone(), prev));
+                    report.push(Resolved::Overwritten(path.clone()));
+                }
+                (_, None) => {}
+            }
+            if let Entry::Dir { mode, .. } = entry {
+                create_dirs(&path, changes)?;
+                modes.push((path, *mode));
+                continue;
+            }
+            if let Some(parent) = path.parent() {
+                create_dirs(parent, changes)?;
+            }
+            std::fs::rename(self.staged(stage, &path)?, &path)?;
+            changes.push(Change::Created(path));
+        }
+        for (path, mode) in modes.into_iter().rev() {
+            if mode.is_some() {
+                let prev = std::fs::metadata(&path)?.permissions();
+                set_mode(&path, mode)?;
+                changes.push(Change::Mode(path, prev));
+            }
+        }
+        Ok(report)
+    }
+
+    /// Where an entry is written to in the stage.
+    fn staged(&self, stage: &Path, path: &Path) -> Result<PathBuf, ExecErr> {
+        match path.strip_prefix(&self.root) {
+            Ok(rel) => Ok(stage.join(rel)),
+            Err(_) => Err(ExecErr::InvalidArgument(format!(
+                "{} is outside of the output dir {}",
+                path.display(),
+                self.root.display()
+            ))),
+        }
+    }
+}
+
+/// Creates the missing dirs of `path`, names like `src/core`
+/// create several at once.
+fn create_dirs(path: &Path, changes: &mut Vec<Change>) -> Result<(), ExecErr> {
+    let missing = path
+        .ancestors()
+        .take_while(|dir| !dir.as_os_str().is_empty() && !dir.exists())
+        .collect::<Vec<&Path>>();
+    for dir in missing.into_iter().rev() {
+        std::fs::create_dir(dir)?;
+        changes.push(Change::Created(dir.to_path_buf()));
+    }
+    Ok(())
+}
+
+/// A change made to the output dir while committing a plan.
+#[derive(Debug)]
+enum Change {
+    Created(PathBuf),
+    /// A path that existed was moved out of the way.
+    Moved(PathBuf, PathBuf),
+    /// The permissions a dir had before.
+    Mode(PathBuf, std::fs::Permissions),
+}
+
+impl Change {
+    /// Undoing is best effort, it runs after something already failed.
+    fn undo(self) {
+        match self {
+            Change::Created(path) => {
+                let _ = match path.symlink_metadata() {
+                    Ok(meta) if meta.is_dir() => std::fs::remove_dir(&path),
+                    _ => std::fs::remove_file(&path),
+                };
+            }
+            Change::Moved(from, to) => {
+                let _ = std::fs::rename(to, from);
+            }
+            Change::Mode(path, prev) => {
+                let _ = std::fs::set_permissions(path, prev);
+            }
+        }
+    }
+}
+
+/// Shows the entries as a tree below the root, with the size of
+/// every file and the paths that already exist.
+impl std::fmt::Display for Plan {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "{}", self.root.display())?;
+        for entry in self.entries.iter() {
+            let rel = entry
+                .path()
+                .strip_prefix(&self.root)
+                .unwrap_or(entry.path());
+            let depth = rel.components().count();
+            let name = rel.file_name().unwrap_or_default().to_string_lossy();
+            write!(f, "{}", "  ".repeat(depth))?;
+            match entry {
+                Entry::Dir { .. } => write!(f, "{}/", name)?,
+                Entry::File { body, .. } => write!(f, "{} ({} B)", name, body.len())?,
+                Entry::Link {
+                    target,
+                    hard: false,
+                    ..
+                } => write!(f, "{} -> {}", name, target)?,
+                Entry::Link { target, .. } => write!(f, "{} => {}", name, target)?,
+            }
+            match entry {
+                entry if entry.conflicts() => {
+                    let action = match self.on_conflict {
+                        OnConflict::Fail => "conflict",
+                        OnConflict::Skip => "skipped",
+                        OnConflict::Overwrite => "overwrites",
+                        OnConflict::Prompt => "asks",
+                        OnConflict::Backup => "backs up",
+                    };
+                    writeln!(f, " ({})", action)?
+                }
+                Entry::Dir { path, .. } if path.exists() => writeln!(f, " (exists)")?,
+                _ => writeln!(f)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Asks whether to overwrite, skip or back up an existing path.
+fn prompt(path: &Path) -> Result<OnConflict, ExecErr> {
+    print!(
+        "{} already exists, overwrite? [y]es, [N]o, [b]ackup: ",
+        path.display()
+    );
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(match answer.trim() {
+        "y" | "Y" | "yes" => OnConflict::Overwrite,
+        "b" | "B" | "backup" => OnConflict::Backup,
+        _ => OnConflict::Skip,
+    })
+}
+
+/// Where an existing path is moved to, `<name>.bak` or `<name>.bak.N`
+/// if that is taken as well.
+fn backup(path: &Path) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let mut bak = path.with_file_name(format!("{}.bak", name));
+    let mut n = 0;
+    while bak.symlink_metadata().is_ok() {
+        n += 1;
+        bak = path.with_file_name(format!("{}.bak.{}", name, n));
+    }
+    bak
+}
+
+#[cfg(unix)]
+fn set_mode(path: &Path, mode: Option<u32>) -> Result<(), ExecErr> {
+    use std::os::unix::fs::PermissionsExt;
+    if let Some(mode) = mode {
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_mode(_: &Path, _: Option<u32>) -> Result<(), ExecErr> {
+    Ok(())
+}
+
+#[cfg(unix)]
+fn symlink(target: &str, path: &Path) -> Result<(), ExecErr> {
+    Ok(std::os::unix::fs::symlink(target, path)?)
+}
+
+#[cfg(not(unix))]
+fn symlink(target: &str, _: &Path) -> Result<(), ExecErr> {
+    Err(ExecErr::InvalidArgument(format!(
+        "cannot link to `{}`, links are only supported on unix",
+        target
+    )))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Entry, Plan, Resolved};
+    use crate::ast::OnConflict;
+    use crate::err::ExecErr;
+    use crate::exec::fixture::Fixture;
+    use crate::exec::Exec;
+
+    #[test]
+    fn test_plan() {
+        let fx = Fixture::new(r#"main dir out { "a.txt": "new", src { "b.txt": "" } }"#);
+        std::fs::create_dir_all(fx.dir.join("out")).unwrap();
+        std::fs::write(fx.dir.join("out/a.txt"), "old").unwrap();
+        let plan = fx.plan().expect("Failed to plan");
+
+        assert!(!fx.dir.join("out/src").exists());
+        assert_eq!(
+            plan.to_string(),
+            format!(
+                "{}\n  out/ (exists)\n    a.txt (3 B) (conflict)\n    src/\n      b.txt (0 B)\n",
+                fx.dir.display()
+            )
+        );
+
+        // Nothing is written as long as a path conflicts.
+        assert!(plan.apply().is_err());
+        assert!(!fx.dir.join("out/src").exists());
+
+        let mut plan = fx.plan().unwrap();
+        plan.on_conflict = crate::ast::OnConflict::Backup;
+        let a = fx.dir.join("out/a.txt");
+        assert_eq!(
+            plan.apply().expect("Failed to apply"),
+            vec![Resolved::BackedUp(a.clone(), fx.dir.join("out/a.txt.bak"))]
+        );
+        assert_eq!(fx.read("out/a.txt"), "new");
+        assert_eq!(fx.read("out/a.txt.bak"), "old");
+        assert!(fx.dir.join("out/src/b.txt").is_file());
+
+        // A missing output dir is created.
+        let out = fx.dir.join("new/out");
+        Exec::run(&fx.syms, fx.main(), Vec::new(), out).expect("Failed to run");
+        assert!(fx.dir.join("new/out/out/src/b.txt").is_file());
+    }
+
+    #[test]
+    fn test_duplicates() {
+        // Caught while planning, so `--dry-run` shows it as well.
+        let fx = Fixture::new(r#"main dir out { "a.txt": "x", src, "a.txt": "y" }"#);
+        match fx.plan() {
+            Err(ExecErr::AlreadyExists(path)) => {
+                assert_eq!(path, fx.dir.join("out/a.txt").display().to_string())
+            }
+            res => panic!("Expected AlreadyExists, got {:?}", res),
+        }
+
+        let fx = Fixture::new(r#"main dir out { src { "a.txt" }, src { "b.txt" } }"#);
+        fx.run().expect("Failed to merge dirs");
+        assert!(fx.dir.join("out/src/b.txt").is_file());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_conflicts() {
+        let fx = Fixture::new(r#"main dir out { "a.txt": "", src { "b.txt": "" } }"#);
+        let out = fx.dir.join("out");
+        std::fs::create_dir_all(&out).unwrap();
+        // A link pointing nowhere and a file where a dir goes.
+        std::os::unix::fs::symlink("missing", out.join("a.txt")).unwrap();
+        std::fs::write(out.join("src"), "").unwrap();
+        let mut plan = fx.plan().unwrap();
+        assert_eq!(plan.conflicts(), [out.join("a.txt"), out.join("src")]);
+
+        plan.on_conflict = crate::ast::OnConflict::Skip;
+        assert_eq!(
+            plan.apply().expect("Failed to apply"),
+            vec![
+                Resolved::Skipped(out.join("a.txt")),
+                Resolved::Skipped(out.join("src")),
+                Resolved::Skipped(out.join("src/b.txt")),
+            ]
+        );
+
+        let mut plan = fx.plan().unwrap();
+        plan.on_conflict = crate::ast::OnConflict::Backup;
+        plan.apply().expect("Failed to apply");
+        assert!(out.join("src/b.txt").is_file());
+        assert!(out.join("src.bak").is_file());
+    }
+
+    #[test]
+    fn test_relative_root() {
+        // The default output is `./`, nothing is written above it.
+        let stage = format!(".tipis-stage-{}", std::process::id());
+        for root in ["", "./"] {
+            let plan = Plan {
+                root: root.into(),
+                on_conflict: OnConflict::Fail,
+                entries: vec![Entry::Dir {
+                    path: root.into(),
+                    mode: None,
+                }],
+            };
+            assert_eq!(plan.apply().expect("Failed to apply"), []);
+            assert!(!std::path::Path::new(&stage).exists());
+        }
+    }
+
+    #[test]
+    fn test_rollback() {
+        let long = "x".repeat(255);
+        let fx = Fixture::new(&format!(
+            r#"
+            cfg {{ [on_conflict=backup] }}
+            main dir out {{ "a.txt": "new", "c.txt": "", src {{ "b.txt": "" }}, "{}": "" }}
+        "#,
+            long
+        ));
+        std::fs::create_dir_all(fx.dir.join("out")).unwrap();
+        std::fs::write(fx.dir.join("out/a.txt"), "old").unwrap();
+        // Backing up a name of the longest length fails half-way.
+        std::fs::write(fx.dir.join("out").join(&long), "old").unwrap();
+        assert!(fx.run().is_err());
+
+        let mut left = std::fs::read_dir(&fx.dir)
+            .unwrap()
+            .chain(std::fs::read_dir(fx.dir.join("out")).unwrap())
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect::<Vec<String>>();
+        left.sort();
+        assert_eq!(left, ["a.txt".to_owned(), "out".to_owned(), long]);
+        assert_eq!(fx.read("out/a.txt"), "old");
+
+        // Dirs created for a missing output dir are removed again.
+        let fx = Fixture::new(r#"main dir out { [hard=true] link "b.txt": "missing" }"#);
+        let out = fx.dir.join("new/out");
+        assert!(Exec::run(&fx.syms, fx.main(), Vec::new(), out).is_err());
+        assert!(!fx.dir.join("new").exists());
+    }
+}
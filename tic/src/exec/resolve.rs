@@ -1,18 +1,25 @@
+use crate::ast::Annot;
 use crate::ast::Ast;
 use crate::ast::BinOp;
 use crate::ast::Dir;
 use crate::ast::Expr;
 use crate::ast::File;
+use crate::ast::For;
 use crate::ast::If;
+use crate::ast::Link;
 use crate::ast::Lit;
 use crate::ast::LitExecutable;
+use crate::ast::LitValue;
+use crate::ast::Op;
 use crate::ast::Ref;
+use crate::ast::{Pipe, UnOp};
 use crate::exec::ExecErr;
 use crate::exec::Syms;
 use crate::exec::Ty;
+use crate::indent;
 
-use super::Key;
-use super::Scope;
+use super::builtin;
+use super::dump::Dump;
 
 #[rustfmt::skip]
 pub trait Resolve {
@@ -26,9 +33,16 @@ impl Resolve for Vec<Expr> {
     where
         O: TryFrom<Lit, Error = ExecErr>,
     {
+        let reindent = syms.cfg().reindent.unwrap_or(true);
         let mut b = String::new();
         for expr in self.into_iter() {
-            b.push_str(&expr.resolve::<String>(syms, target, args.clone())?);
+            let text = matches!(expr, Expr::Lit(Lit::String(_)));
+            let val = expr.resolve::<String>(syms, target, args.clone())?;
+            if reindent && !text {
+                indent::push_indented(&mut b, &val);
+            } else {
+                b.push_str(&val);
+            }
         }
         O::try_from(Lit::String(b))
     }
@@ -41,8 +55,20 @@ impl Resolve for Expr {
     {
         match self {
             Expr::Ref(r) => r.resolve(syms, target, args),
+            Expr::Lit(Lit::BinOp(b)) => b.resolve(syms, target, args),
+            Expr::Lit(Lit::UnOp(u)) => u.resolve(syms, target, args),
+            Expr::Lit(Lit::Pipe(p)) => p.resolve(syms, target, args),
             Expr::Lit(l) => O::try_from(l),
             Expr::If(i) => i.resolve(syms, target, args),
+            Expr::For(f) => f.resolve(syms, target, args),
+            Expr::List(l) => {
+                let mut items = Vec::with_capacity(l.len());
+                for expr in l.into_iter() {
+                    let LitValue(val) = expr.resolve(syms, &Ty::Unknown, args.clone())?;
+                    items.push(val);
+                }
+                O::try_from(Lit::List(items))
+            }
         }
     }
 }
@@ -52,34 +78,168 @@ impl Resolve for Ref {
     where
         O: TryFrom<Lit, Error = ExecErr>,
     {
-        let sym = match syms.get(&Key(self.name.clone(), Scope::Global)) {
-            None => match args.iter().find(|(name, _)| name == &self.name).cloned() {
-                Some((_, expr)) => return expr.resolve(syms, target, args),
+        // Later args shadow earlier ones, so bindings introduced
+        // by loops take precedence over the command line args.
+        if let Some((_, expr)) = args
+            .iter()
+            .rev()
+            .find(|(name, _)| name == &self.name)
+            .cloned()
+        {
+            return expr.resolve(syms, target, args);
+        }
+
+        let sym = match syms.lookup(&self.name, &args) {
+            Some(s) => s,
+            None => match builtin::lookup(&self.name) {
+                Some(func) => {
+                    let mut vals = Vec::with_capacity(self.args.len());
+                    for (_, expr) in self.args.into_iter() {
+                        let LitValue(val) = expr.resolve(syms, &Ty::Unknown, args.clone())?;
+                        vals.push(val);
+                    }
+                    return O::try_from(func(vals)?);
+                }
                 None => return Err(ExecErr::NotFound(self.name)),
             },
-            Some(s) => s,
         };
 
-        let resolve_to = if &sym.ty() == target || target == &Ty::Unknown {
+        let resolve_to = if &sym.ty() == target || target == &Ty::Unknown || sym.ty() == Ty::Unknown
+        {
             sym.ty()
         } else {
             return Err(ExecErr::InvalidType(self.name, sym.ty().to_string()));
         };
 
+        let args = self.bind(syms, args)?;
         match sym.val {
-            Ast::Req(ref req) => O::try_from(Lit::String(req.expr.to_owned())),
+            Ast::Req(ref req) => match syms.args.iter().find(|(name, _)| name == &req.name) {
+                Some((_, expr)) => expr.clone().resolve(syms, &resolve_to, args),
+                None => match req.default(syms) {
+                    Some(lit) => O::try_from(lit?),
+                    None => Err(ExecErr::NotFound(self.name)),
+                },
+            },
             Ast::Lit(ref lit) => O::try_from(Lit::String(lit.to_owned())),
-            Ast::Let(ref let_) => {
-                let mut b = String::new();
-                for expr in let_.expr.clone().into_iter() {
-                    b.push_str(&expr.resolve::<String>(syms, &resolve_to, args.clone())?);
-                }
-                O::try_from(Lit::String(b))
+            Ast::Let(ref let_) if skipped(syms, &let_.annots, args.clone())? => {
+                O::try_from(resolve_to.empty())
+            }
+            // A let of a single expression keeps its value, e.g. a list.
+            Ast::Let(ref let_) if let_.expr.len() == 1 => {
+                let_.expr[0].clone().resolve(syms, &resolve_to, args)
             }
+            Ast::Let(ref let_) => let_.expr.clone().resolve(syms, &resolve_to, args),
             Ast::Dir(ref dir) => O::try_from(Lit::Dir(dir.clone())),
             Ast::File(ref file) => O::try_from(Lit::File(file.clone())),
             Ast::Ref(ref ref_) => ref_.clone().resolve(syms, &resolve_to, args),
+            Ast::Use(_) | Ast::Cfg(_) => Err(ExecErr::NotFound(self.name)),
+        }
+    }
+}
+
+/// Whether a `[skip_if=..]` annotation holds in the given args.
+pub fn skipped(syms: &Syms, annots: &[Annot], args: Vec<(String, Expr)>) -> Result<bool, ExecErr> {
+    match Annot::get(annots, "skip_if") {
+        Some(cond) => cond.clone().resolve::<bool>(syms, &Ty::Unknown, args),
+        None => Ok(false),
+    }
+}
+
+impl Ref {
+    /// Binds the arguments of this reference to the params of the
+    /// referenced symbol, by position or by name. The arguments are
+    /// evaluated in the calling environment, while the symbol gets a
+    /// fresh one holding only its params and the scope it was
+    /// declared in. Missing params get their defaults.
+    pub fn bind(
+        &self,
+        syms: &Syms,
+        args: Vec<(String, Expr)>,
+    ) -> Result<Vec<(String, Expr)>, ExecErr> {
+        // Builtins evaluate their arguments themselves.
+        let sym = match syms.lookup(&self.name, &args) {
+            Some(sym) => sym,
+            None => return Ok(args),
+        };
+        let params = sym.params();
+        let invalid = |msg: String| ExecErr::InvalidArgument(msg);
+
+        let mut given = vec![None; params.len()];
+        let mut positional = 0;
+        for (name, expr) in self.args.iter() {
+            let ix = if name.is_empty() {
+                positional += 1;
+                if positional > params.len() {
+                    return Err(invalid(format!(
+                        "`{}` takes {} argument(s), got {}",
+                        self.name,
+                        params.len(),
+                        self.args.len()
+                    )));
+                }
+                positional - 1
+            } else {
+                params
+                    .iter()
+                    .position(|param| &param.name == name)
+                    .ok_or_else(|| invalid(format!("`{}` has no param `{}`", self.name, name)))?
+            };
+            let param = &params[ix];
+            if given[ix].is_some() {
+                return Err(invalid(format!(
+                    "`{}` of `{}` is given twice",
+                    param.name, self.name
+                )));
+            }
+            let LitValue(val) = expr.clone().resolve(syms, &Ty::Unknown, args.clone())?;
+            given[ix] = Some(check_arg(val, &param.ty).map_err(|got| {
+                invalid(format!(
+                    "`{}` of `{}` expects {}, got {}",
+                    param.name, self.name, param.ty, got
+                ))
+            })?);
+        }
+
+        let mut env = vec![sym.scope.arg()];
+        let mut missing = Vec::new();
+        for (param, val) in params.into_iter().zip(given) {
+            match val {
+                Some(val) => env.push((param.name, Expr::Lit(val))),
+                None => missing.push(param),
+            }
         }
+        // Defaults may refer to the params that were given.
+        for param in missing.into_iter() {
+            let default = param.default.ok_or_else(|| {
+                invalid(format!(
+                    "`{}` is missing `{}: {}`",
+                    self.name, param.name, param.ty
+                ))
+            })?;
+            let LitValue(val) = default.resolve(syms, &param.ty, env.clone())?;
+            env.push((param.name, Expr::Lit(val)));
+        }
+        Ok(env)
+    }
+}
+
+/// Checks an argument against the type of its param, ints are
+/// accepted as strings. On failure returns the offending value.
+fn check_arg(lit: Lit, ty: &Ty) -> Result<Lit, String> {
+    match (ty, lit) {
+        (Ty::Unknown, lit) => Ok(lit),
+        (Ty::String, lit @ (Lit::String(_) | Lit::Raw(_))) => Ok(lit),
+        (Ty::String, Lit::Int(i)) => Ok(Lit::String(i)),
+        (Ty::Int, lit @ Lit::Int(_)) => Ok(lit),
+        (Ty::Dir, lit @ Lit::Dir(_)) => Ok(lit),
+        (Ty::File, lit @ Lit::File(_)) => Ok(lit),
+        (Ty::List(elem), Lit::List(items)) => Ok(Lit::List(
+            items
+                .into_iter()
+                .map(|item| check_arg(item, elem))
+                .collect::<Result<Vec<Lit>, String>>()?,
+        )),
+        (_, lit) => Err(lit.dump()),
     }
 }
 
@@ -88,7 +248,7 @@ impl Resolve for Lit {
     where
         O: TryFrom<Lit, Error = ExecErr>,
     {
-        return O::try_from(self);
+        O::try_from(self)
     }
 }
 
@@ -97,7 +257,7 @@ impl Resolve for String {
     where
         O: TryFrom<Lit, Error = ExecErr>,
     {
-        return O::try_from(Lit::String(self));
+        O::try_from(Lit::String(self))
     }
 }
 
@@ -106,7 +266,7 @@ impl Resolve for File {
     where
         O: TryFrom<Lit, Error = ExecErr>,
     {
-        return O::try_from(Lit::File(self));
+        O::try_from(Lit::File(self))
     }
 }
 
@@ -115,7 +275,16 @@ impl Resolve for Dir {
     where
         O: TryFrom<Lit, Error = ExecErr>,
     {
-        return O::try_from(Lit::Dir(self));
+        O::try_from(Lit::Dir(self))
+    }
+}
+
+impl Resolve for Link {
+    fn resolve<O>(self, _: &Syms, _: &Ty, _: Vec<(String, Expr)>) -> Result<O, ExecErr>
+    where
+        O: TryFrom<Lit, Error = ExecErr>,
+    {
+        O::try_from(Lit::Link(self))
     }
 }
 
@@ -138,14 +307,9 @@ impl Resolve for If {
     where
         O: TryFrom<Lit, Error = ExecErr>,
     {
-        let cond = self.cond.resolve::<bool>(syms, target, args.clone())?;
-        let cond = match cond {
-            Lit::String(st) => st,
-            _ => return Err(ExecErr::InvalidType("if".into(), "str".into())),
-        };
-        let cond = cond
-            .parse::<bool>()
-            .map_err(|_| ExecErr::InvalidType("if".into(), "bool".into()))?;
+        let cond = self
+            .cond
+            .resolve::<bool>(syms, &Ty::Unknown, args.clone())?;
         if cond {
             self.then.resolve(syms, target, args)
         } else {
@@ -154,11 +318,357 @@ impl Resolve for If {
     }
 }
 
-impl Resolve for BinOp {
+impl Resolve for For {
     fn resolve<O>(self, syms: &Syms, target: &Ty, args: Vec<(String, Expr)>) -> Result<O, ExecErr>
     where
         O: TryFrom<Lit, Error = ExecErr>,
     {
-        O::try_from(Lit::BinOp(self))
+        let items = self
+            .iter
+            .resolve::<Vec<Lit>>(syms, &Ty::Unknown, args.clone())?;
+        let mut b = String::new();
+        for item in items.into_iter() {
+            let mut args = args.clone();
+            args.push((self.var.clone(), Expr::Lit(item)));
+            b.push_str(&self.body.clone().resolve::<String>(syms, target, args)?);
+        }
+        O::try_from(Lit::String(b))
+    }
+}
+
+impl Resolve for BinOp {
+    fn resolve<O>(self, syms: &Syms, _: &Ty, args: Vec<(String, Expr)>) -> Result<O, ExecErr>
+    where
+        O: TryFrom<Lit, Error = ExecErr>,
+    {
+        if let Op::And | Op::Or = self.op {
+            let lhs = self.lhs.resolve::<bool>(syms, &Ty::Unknown, args.clone())?;
+            // Short circuit, `rhs` is only evaluated if it can change the result.
+            if lhs == (self.op == Op::Or) {
+                return O::try_from(Lit::Bool(lhs));
+            }
+            let rhs = self.rhs.resolve::<bool>(syms, &Ty::Unknown, args)?;
+            return O::try_from(Lit::Bool(rhs));
+        }
+
+        let LitValue(lhs) = self.lhs.resolve(syms, &Ty::Unknown, args.clone())?;
+        let LitValue(rhs) = self.rhs.resolve(syms, &Ty::Unknown, args)?;
+        O::try_from(eval_binop(self.op, lhs, rhs)?)
+    }
+}
+
+impl Resolve for Pipe {
+    fn resolve<O>(self, syms: &Syms, _: &Ty, args: Vec<(String, Expr)>) -> Result<O, ExecErr>
+    where
+        O: TryFrom<Lit, Error = ExecErr>,
+    {
+        let func = builtin::lookup(&self.filter)
+            .ok_or_else(|| ExecErr::NotFound(format!("filter `{}`", self.filter)))?;
+        let exprs = std::iter::once(*self.val).chain(self.args.into_iter().map(|(_, expr)| expr));
+        let mut vals = Vec::new();
+        for expr in exprs {
+            let LitValue(val) = expr.resolve(syms, &Ty::Unknown, args.clone())?;
+            vals.push(val);
+        }
+        O::try_from(func(vals)?)
+    }
+}
+
+impl Resolve for UnOp {
+    fn resolve<O>(self, syms: &Syms, _: &Ty, args: Vec<(String, Expr)>) -> Result<O, ExecErr>
+    where
+        O: TryFrom<Lit, Error = ExecErr>,
+    {
+        match self.op {
+            Op::Not => {
+                let val = self.expr.resolve::<bool>(syms, &Ty::Unknown, args)?;
+                O::try_from(Lit::Bool(!val))
+            }
+            Op::Sub => {
+                let LitValue(val) = self.expr.resolve(syms, &Ty::Unknown, args)?;
+                let val = int(&val)?
+                    .checked_neg()
+                    .ok_or_else(|| ExecErr::InvalidArgument(format!("-{}", val.dump())))?;
+                O::try_from(Lit::Int(val.to_string()))
+            }
+            op => Err(ExecErr::InvalidArgument(format!(
+                "`{}` is not a unary operator",
+                op
+            ))),
+        }
+    }
+}
+
+fn eval_binop(op: Op, lhs: Lit, rhs: Lit) -> Result<Lit, ExecErr> {
+    let invalid = || ExecErr::InvalidArgument(format!("{} {} {}", lhs.dump(), op, rhs.dump()));
+    Ok(match op {
+        Op::Add | Op::Sub | Op::Mul | Op::Div => match (&lhs, &rhs) {
+            (Lit::Int(_), Lit::Int(_)) => {
+                let (l, r) = (int(&lhs)?, int(&rhs)?);
+                let val = match op {
+                    Op::Add => l.checked_add(r),
+                    Op::Sub => l.checked_sub(r),
+                    Op::Mul => l.checked_mul(r),
+                    _ => l.checked_div(r),
+                };
+                Lit::Int(val.ok_or_else(invalid)?.to_string())
+            }
+            (Lit::String(_), Lit::String(_) | Lit::Int(_)) | (Lit::Int(_), Lit::String(_))
+                if op == Op::Add =>
+            {
+                let mut buf = String::try_from(lhs.clone())?;
+                buf.push_str(&String::try_from(rhs.clone())?);
+                Lit::String(buf)
+            }
+            _ => return Err(invalid()),
+        },
+        Op::Eq => Lit::Bool(eq(&lhs, &rhs)),
+        Op::Neq => Lit::Bool(!eq(&lhs, &rhs)),
+        Op::Lt | Op::Gt | Op::Le | Op::Ge => {
+            let ord = match (&lhs, &rhs) {
+                (Lit::Int(_), Lit::Int(_)) => int(&lhs)?.cmp(&int(&rhs)?),
+                (Lit::String(l) | Lit::Raw(l), Lit::String(r) | Lit::Raw(r)) => l.cmp(r),
+                _ => return Err(invalid()),
+            };
+            Lit::Bool(match op {
+                Op::Lt => ord.is_lt(),
+                Op::Gt => ord.is_gt(),
+                Op::Le => ord.is_le(),
+                _ => ord.is_ge(),
+            })
+        }
+        Op::Index => builtin::index(lhs.clone(), int(&rhs)?)?,
+        Op::And | Op::Or | Op::Not => return Err(invalid()),
+    })
+}
+
+/// Ints compare by value, so `007 == 7`, everything else structurally.
+fn eq(lhs: &Lit, rhs: &Lit) -> bool {
+    match (int(lhs), int(rhs)) {
+        (Ok(l), Ok(r)) => l == r,
+        _ => lhs == rhs,
+    }
+}
+
+fn int(lit: &Lit) -> Result<i64, ExecErr> {
+    match lit {
+        Lit::Int(i) => i
+            .parse::<i64>()
+            .map_err(|_| ExecErr::InvalidType(i.clone(), Ty::Int.to_string())),
+        l => Err(ExecErr::InvalidType(l.dump(), Ty::Int.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Expr, Lit, Ty};
+    use crate::err::ExecErr;
+    use crate::exec::{resolve::Resolve, Key, Scope, Syms};
+    use crate::syntax::Syntax;
+
+    fn render(src: &str, name: &str, args: Vec<(String, Expr)>) -> String {
+        let mut syms = Syms::new(args.clone());
+        let mut syn = Syntax::new(src.as_bytes());
+        syms.add_all_ast(syn.parse_all().expect("Failed to parse"))
+            .expect("Failed to add symbols");
+        let sym = syms.get(&Key(name.into(), Scope::Global)).unwrap().clone();
+        match sym.val {
+            crate::ast::Ast::Let(l) => l.expr.resolve::<String>(&syms, &Ty::String, args),
+            _ => panic!("Expected let"),
+        }
+        .expect("Failed to resolve")
+    }
+
+    #[test]
+    fn test_for() {
+        let names = Lit::List(vec![Lit::String("a".into()), Lit::String("b".into())]);
+        let out = render(
+            r#"let hey = {{@for name in names {{<{$name$}>}}}};"#,
+            "hey",
+            vec![("names".into(), Expr::Lit(names))],
+        );
+        assert_eq!(out, "<a><b>");
+    }
+
+    #[test]
+    fn test_for_shadows_args() {
+        let names = Lit::List(vec![Lit::String("a".into())]);
+        let out = render(
+            r#"let hey = {{@for name in names {{{$name$}}} {$name$}}};"#,
+            "hey",
+            vec![
+                ("name".into(), Expr::Lit(Lit::String("outer".into()))),
+                ("names".into(), Expr::Lit(names)),
+            ],
+        );
+        assert_eq!(out, "a outer");
+    }
+
+    #[test]
+    fn test_if() {
+        let src = r#"
+            let hey = {{@for name in names {{@if name == "kenobi" {{General Kenobi!}}
+                @elseif name == "" {{?}}
+                @else {{Hello There {$name$}.}} }}}};
+        "#;
+        let names = Lit::List(vec![
+            Lit::String("kenobi".into()),
+            Lit::String("".into()),
+            Lit::String("grievous".into()),
+        ]);
+        let out = render(src, "hey", vec![("names".into(), Expr::Lit(names))]);
+        assert_eq!(out, "General Kenobi! ? Hello There grievous. ");
+    }
+
+    #[test]
+    fn test_if_truthy() {
+        let src = r#"let hey = {{@if docker {{with}} @else {{without}}}};"#;
+        let arg = |v: &str| vec![("docker".into(), Expr::Lit(Lit::String(v.into())))];
+        assert_eq!(render(src, "hey", arg("true")), "with");
+        assert_eq!(render(src, "hey", arg("false")), "without");
+        assert_eq!(render(src, "hey", arg("")), "without");
+    }
+
+    #[test]
+    fn test_binop() {
+        let src = r#"
+            let greet(who: str) = {{Hello {$ who $}}};
+            let hey = {{{$ 1 + 2 * 3 $} {$ (1 + 2) * 3 $} {$ 7 / 2 - -1 $} {$ "v" + n $}}};
+            let cmp = {{{$ n > 2 && !(n >= 10) || false $} {$ "a" < "b" $} {$ n == 007 $}}};
+            let call = {{{$ greet(who: name + "!") $}}};
+            let cond = {{@if n * 2 == 14 && name != "" {{yes}} @else {{no}}}};
+        "#;
+        let args = vec![
+            ("n".into(), Expr::Lit(Lit::Int("7".into()))),
+            ("name".into(), Expr::Lit(Lit::String("kenobi".into()))),
+        ];
+        assert_eq!(render(src, "hey", args.clone()), "7 9 4 v7");
+        assert_eq!(render(src, "cmp", args.clone()), "true true true");
+        assert_eq!(render(src, "call", args.clone()), "Hello kenobi!");
+        assert_eq!(render(src, "cond", args), "yes");
+    }
+
+    #[test]
+    fn test_binop_invalid() {
+        let mut syms = Syms::new(Vec::new());
+        let mut syn =
+            Syntax::new(r#"let hey = {{{$ "a" * 2 $}}}; let div = {{{$ 1 / 0 $}}};"#.as_bytes());
+        syms.add_all_ast(syn.parse_all().expect("Failed to parse"))
+            .expect("Failed to add symbols");
+        for name in ["hey", "div"] {
+            let sym = syms.get(&Key(name.into(), Scope::Global)).unwrap().clone();
+            let res = match sym.val {
+                crate::ast::Ast::Let(l) => l.expr.resolve::<String>(&syms, &Ty::String, Vec::new()),
+                _ => panic!("Expected let"),
+            };
+            assert!(matches!(res, Err(ExecErr::InvalidArgument(_))), "{:?}", res);
+        }
+    }
+
+    #[test]
+    fn test_list() {
+        let src = r#"
+            let svcs = ["api", "worker", "db"];
+            let hey = {{@for s in svcs {{{$ s $};}} {$ len(svcs) $} {$ svcs[1] $} {$ svcs[-1] $}}};
+            let ends = {{{$ first(names) $}-{$ last(names) $}: {$ join(names, "/") $}}};
+        "#;
+        assert_eq!(render(src, "hey", Vec::new()), "api;worker;db; 3 worker db");
+
+        let names = Lit::List(vec![Lit::String("a".into()), Lit::String("b".into())]);
+        let args = vec![("names".to_string(), Expr::Lit(names))];
+        assert_eq!(render(src, "ends", args), "a-b: a/b");
+
+        // Strings are not lists, only list reqs given on the command
+        // line are split on commas.
+        let syms = Syms::new(Vec::new());
+        let text = Expr::Lit(Lit::String("x, y, z".into()));
+        assert!(text
+            .resolve::<Vec<Lit>>(&syms, &Ty::Unknown, Vec::new())
+            .is_err());
+    }
+
+    #[test]
+    fn test_defaults() {
+        let src = r#"
+            req greeting: str = "Hello";
+            req license: str?;
+            let greet(name: str = "world", punct: str = "!") = {{{$ greeting $} {$ name $}{$ punct $}}};
+            let hey = {{{$ greet() $} {$ greet(name: "there", punct: ".") $}{$ license $}}};
+        "#;
+        assert_eq!(render(src, "hey", Vec::new()), "Hello world! Hello there.");
+    }
+
+    #[test]
+    fn test_filters() {
+        let src = r#"
+            let names = {{{$ name | snake_case $} {$ name | pascal_case $} {$ name | kebab_case | upper $}}};
+            let camel = {{{$ "  HTTPServer error " | trim | camel_case $}, {$ ["a", "b"] | join("+") $}}};
+        "#;
+        let args = vec![(
+            "name".to_string(),
+            Expr::Lit(Lit::String("myApp v2".into())),
+        )];
+        assert_eq!(render(src, "names", args), "my_app_v2 MyAppV2 MY-APP-V2");
+        assert_eq!(render(src, "camel", Vec::new()), "httpServerError, a+b");
+
+        // Names in scope do not shadow filters.
+        let src = r#"
+            let shout(upper: str) = {{{$ upper | upper $}}};
+            let loud = {{@for trim in [" a "] {{{$ shout(trim | trim) $}}}}};
+        "#;
+        assert_eq!(render(src, "loud", Vec::new()), "A");
+    }
+
+    #[test]
+    fn test_call() {
+        let src = r#"
+            let pair(a: str, b: int = 2) = {{{$ a $}={$ b $}}};
+            let leak = {{{$ x $}}};
+            let ok = {{{$ pair("x", 1) $} {$ pair(b: 3, a: "y") $} {$ pair(4) $}}};
+            let leaks = {{@for x in ["a"] {{{$ leak $}}}}};
+            let extra = {{{$ pair("x", 1, 2) $}}};
+            let unknown = {{{$ pair(c: 1) $}}};
+            let twice = {{{$ pair("x", a: "y") $}}};
+            let missing = {{{$ pair(b: 1) $}}};
+            let mistyped = {{{$ pair("x", b: "two") $}}};
+        "#;
+        assert_eq!(render(src, "ok", Vec::new()), "x=1 y=3 4=2");
+
+        let mut syms = Syms::new(Vec::new());
+        let mut syn = Syntax::new(src.as_bytes());
+        syms.add_all_ast(syn.parse_all().expect("Failed to parse"))
+            .expect("Failed to add symbols");
+        let err = |name: &str| {
+            let sym = syms.get(&Key(name.into(), Scope::Global)).unwrap().clone();
+            match sym.val {
+                crate::ast::Ast::Let(l) => l
+                    .expr
+                    .resolve::<String>(&syms, &Ty::String, Vec::new())
+                    .expect_err("Expected an error")
+                    .to_string(),
+                _ => panic!("Expected let"),
+            }
+        };
+        assert_eq!(err("leaks"), "Exec: NotFound :: x");
+        assert_eq!(
+            err("extra"),
+            "Exec: InvalidArgument :: `pair` takes 2 argument(s), got 3"
+        );
+        assert_eq!(
+            err("unknown"),
+            "Exec: InvalidArgument :: `pair` has no param `c`"
+        );
+        assert_eq!(
+            err("twice"),
+            "Exec: InvalidArgument :: `a` of `pair` is given twice"
+        );
+        assert_eq!(
+            err("missing"),
+            "Exec: InvalidArgument :: `pair` is missing `a: str`"
+        );
+        assert_eq!(
+            err("mistyped"),
+            "Exec: InvalidArgument :: `b` of `pair` expects int, got \"two\""
+        );
     }
 }
@@ -5,6 +5,12 @@ pub struct Fifo<E: Copy> {
     fifo: SmallVec<E, 20>,
 }
 
+impl<E: Copy> Default for Fifo<E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<E: Copy> Fifo<E> {
     pub fn new() -> Self {
         Self {
@@ -0,0 +1,260 @@
+use crate::ast::{Expr, Lit};
+
+/// Removes the indentation a `{{ .. }}` body inherits from the
+/// template source.
+///
+/// The blank first line after `{{` and the last line before `}}`
+/// are dropped, as are the lines only holding an `@if` or `@for`.
+/// If the body started on a new line, the indentation common to all
+/// remaining lines is stripped, including nested `@if` and `@for`.
+pub fn dedent(mut body: Vec<Expr>) -> Vec<Expr> {
+    trim_blocks(&mut body);
+    trim_last_line(&mut body, true);
+    // Text right after `{{` sets the indentation itself.
+    if !trim_first_line(&mut body) {
+        return body;
+    }
+
+    let mut margin = None;
+    measure(&body, &mut true, &mut 0, &mut margin);
+    if let Some(margin) = margin.filter(|m| *m > 0) {
+        strip(&mut body, margin, &mut true, &mut 0);
+    }
+    body
+}
+
+/// Pushes a resolved value onto `buf`, indenting all but its first
+/// line to the column it is inserted at.
+pub fn push_indented(buf: &mut String, val: &str) {
+    let line = &buf[buf.rfind('\n').map_or(0, |ix| ix + 1)..];
+    if !val.contains('\n') || line.is_empty() {
+        buf.push_str(val);
+        return;
+    }
+    let pad = line
+        .chars()
+        .map(|ch| if ch == '\t' { '\t' } else { ' ' })
+        .collect::<String>();
+    for (ix, ln) in val.split('\n').enumerate() {
+        if ix > 0 {
+            buf.push('\n');
+            if !ln.is_empty() {
+                buf.push_str(&pad);
+            }
+        }
+        buf.push_str(ln);
+    }
+}
+
+/// Applies the standalone line rule to every `@if` and `@for` that
+/// is alone on its line, so they do not leave blank lines behind.
+fn trim_blocks(body: &mut [Expr]) {
+    for ix in 0..body.len() {
+        if !matches!(body[ix], Expr::If(_) | Expr::For(_)) {
+            continue;
+        }
+        let before = ix
+            .checked_sub(1)
+            .and_then(|i| text(&body[i]))
+            .and_then(|s| s.rfind('\n').map(|nl| &s[nl + 1..]))
+            .is_some_and(is_blank);
+        let after = body
+            .get(ix + 1)
+            .and_then(text)
+            .is_some_and(|s| s.find('\n').is_some_and(|nl| is_blank(&s[..nl])));
+        if !before || !after {
+            continue;
+        }
+
+        if let Some(Expr::Lit(Lit::String(s))) = body.get_mut(ix - 1) {
+            s.truncate(s.rfind('\n').unwrap() + 1);
+        }
+        if let Some(Expr::Lit(Lit::String(s))) = body.get_mut(ix + 1) {
+            s.drain(..=s.find('\n').unwrap());
+        }
+        for branch in branches(&mut body[ix]) {
+            trim_blocks(branch);
+            trim_first_line(branch);
+            trim_last_line(branch, false);
+        }
+    }
+}
+
+/// Drops a blank first line, returns whether it did.
+fn trim_first_line(body: &mut [Expr]) -> bool {
+    if let Some(Expr::Lit(Lit::String(s))) = body.first_mut() {
+        if let Some(nl) = s.find('\n').filter(|nl| is_blank(&s[..*nl])) {
+            s.drain(..=nl);
+            return true;
+        }
+    }
+    false
+}
+
+/// Drops the whitespace after the last newline, and that newline
+/// as well if `newline` is set.
+fn trim_last_line(body: &mut [Expr], newline: bool) {
+    if let Some(Expr::Lit(Lit::String(s))) = body.last_mut() {
+        if let Some(nl) = s.rfind('\n').filter(|nl| is_blank(&s[nl + 1..])) {
+            s.truncate(if newline { nl } else { nl + 1 });
+        }
+    }
+}
+
+/// Finds the smallest indentation of the non-blank lines.
+fn measure(body: &[Expr], at_start: &mut bool, cur: &mut usize, margin: &mut Option<usize>) {
+    for expr in body.iter() {
+        match expr {
+            Expr::Lit(Lit::String(s)) => {
+                for ch in s.chars() {
+                    match ch {
+                        '\n' => {
+                            *at_start = true;
+                            *cur = 0;
+                        }
+                        ' ' | '\t' if *at_start => *cur += 1,
+                        _ if *at_start => {
+                            *margin = Some(margin.map_or(*cur, |m| m.min(*cur)));
+                            *at_start = false;
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            Expr::If(i) => {
+                let (mut start, mut c) = (*at_start, *cur);
+                measure(&i.els, &mut start, &mut c, margin);
+                measure(&i.then, at_start, cur, margin);
+            }
+            Expr::For(f) => measure(&f.body, at_start, cur, margin),
+            _ if *at_start => {
+                *margin = Some(margin.map_or(*cur, |m| m.min(*cur)));
+                *at_start = false;
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Removes up to `margin` whitespace characters from every line.
+fn strip(body: &mut [Expr], margin: usize, at_start: &mut bool, cur: &mut usize) {
+    for expr in body.iter_mut() {
+        match expr {
+            Expr::Lit(Lit::String(s)) => {
+                let mut buf = String::with_capacity(s.len());
+                for ch in s.chars() {
+                    match ch {
+                        '\n' => {
+                            *at_start = true;
+                            *cur = 0;
+                        }
+                        ' ' | '\t' if *at_start && *cur < margin => {
+                            *cur += 1;
+                            continue;
+                        }
+                        _ => *at_start = false,
+                    }
+                    buf.push(ch);
+                }
+                *s = buf;
+            }
+            Expr::If(i) => {
+                let (mut start, mut c) = (*at_start, *cur);
+                strip(&mut i.els, margin, &mut start, &mut c);
+                strip(&mut i.then, margin, at_start, cur);
+            }
+            Expr::For(f) => strip(&mut f.body, margin, at_start, cur),
+            _ => *at_start = false,
+        }
+    }
+}
+
+fn branches(expr: &mut Expr) -> Vec<&mut Vec<Expr>> {
+    match expr {
+        Expr::If(i) => vec![&mut i.then, &mut i.els],
+        Expr::For(f) => vec![&mut f.body],
+        _ => Vec::new(),
+    }
+}
+
+fn text(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::Lit(Lit::String(s)) => Some(s),
+        _ => None,
+    }
+}
+
+fn is_blank(s: &str) -> bool {
+    s.chars().all(|ch| ch == ' ' || ch == '\t' || ch == '\r')
+}
+
+#[cfg(test)]
+mod test {
+    use crate::ast::{Expr, Lit};
+    use crate::exec::{resolve::Resolve, Syms};
+    use crate::syntax::Syntax;
+
+    fn render(src: &str, args: Vec<(String, Expr)>) -> String {
+        let mut syms = Syms::new(args.clone());
+        let mut syn = Syntax::new(src.as_bytes());
+        syms.add_all_ast(syn.parse_all().expect("Failed to parse"))
+            .expect("Failed to add symbols");
+        match syms.main() {
+            Some(crate::ast::Ast::Let(l)) => l
+                .expr
+                .resolve::<String>(&syms, &crate::ast::Ty::String, args)
+                .expect("Failed to resolve"),
+            _ => panic!("Expected main let"),
+        }
+    }
+
+    #[test]
+    fn test_dedent() {
+        let src = r#"
+            let body = {{
+                return nil
+            }};
+            main let go = {{
+                package main
+
+                func main() {
+                    {$ body $}
+                    @for x in xs {{
+                    fmt.Println("{$ x $}")
+                    }}
+                }
+            }};
+        "#;
+        let xs = Lit::List(vec![Lit::String("a".into()), Lit::String("b".into())]);
+        let out = render(src, vec![("xs".into(), Expr::Lit(xs))]);
+        assert_eq!(
+            out,
+            "package main\n\nfunc main() {\n    return nil\n    fmt.Println(\"a\")\n    fmt.Println(\"b\")\n}"
+        );
+    }
+
+    #[test]
+    fn test_reindent() {
+        let src = r#"
+            let fields = {{
+                a int
+                b int
+            }};
+            main let go = {{
+                type T struct {
+                    {$ fields $}
+                }
+            }};
+        "#;
+        assert_eq!(
+            render(src, Vec::new()),
+            "type T struct {\n    a int\n    b int\n}"
+        );
+
+        let src = format!("cfg {{ [reindent=false] }}\n{}", src);
+        assert_eq!(
+            render(&src, Vec::new()),
+            "type T struct {\n    a int\nb int\n}"
+        );
+    }
+}
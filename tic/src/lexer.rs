@@ -10,6 +10,8 @@ pub struct Cx<'a> {
     pub col: usize,
     pub ch: char,
     pub pending: Fifo<Token<'a>>,
+    /// Lines of the `///` comments right before the last token.
+    pub doc: Vec<&'a str>,
 }
 
 pub struct Lexer<'a> {
@@ -28,6 +30,7 @@ impl<'a> Lexer<'a> {
                 col: 0,
                 ch: '\0',
                 pending: Fifo::new(),
+                doc: Vec::new(),
             },
             tmpcx: None,
         }
@@ -87,6 +90,27 @@ impl<'a> Lexer<'a> {
         self.lx_str(false)
     }
 
+    /// Continues a `"` string after an insertion, the closing
+    /// quote, if reached, is queued after the returned string.
+    pub fn try_lx_dq_str(&mut self) -> Result<Token<'a>, Trace<'a, LxErr>> {
+        self.reset();
+        let string = self.lx_str(true)?;
+        self.lx_dq_closer();
+        Ok(string)
+    }
+
+    /// Queues the closing quote of a `"` string, unless the
+    /// string stopped at an insertion.
+    fn lx_dq_closer(&mut self) {
+        if let Some(b'"') = self.peek() {
+            self.take();
+            self.cx.pending.push(Token {
+                src: self.src_single(),
+                kind: TokKind::CloserDQuote,
+            });
+        }
+    }
+
     #[rustfmt::skip]
     pub fn try_lx_ident(&mut self) -> Result<Token<'a>, Trace<'a, LxErr>> {
         self.reset();
@@ -96,6 +120,40 @@ impl<'a> Lexer<'a> {
         self.lx_ident()
     }
 
+    /// Skips whitespace and consumes `@else` or `@elseif` if
+    /// that is what follows, otherwise leaves the lexer untouched.
+    ///
+    /// Used after the body of an `@if`, where the source may either
+    /// be string content or further declarations.
+    pub fn try_lx_else(&mut self) -> Option<Token<'a>> {
+        self.reset();
+        if self.cx.pending.has_some() {
+            return None;
+        }
+        let cx = self.cx.clone();
+        self.skip_ws();
+        if let Some(b'@') = self.peek() {
+            self.take();
+            if let Ok(tok) = self.lx_ident() {
+                if let TokKind::Else | TokKind::ElseIf = tok.kind {
+                    return Some(tok);
+                }
+            }
+        }
+        self.cx = cx;
+        None
+    }
+
+    /// Takes the doc comment in front of the last lexed token.
+    pub fn take_doc(&mut self) -> Option<String> {
+        if self.cx.doc.is_empty() {
+            return None;
+        }
+        let doc = self.cx.doc.join("\n");
+        self.cx.doc.clear();
+        Some(doc)
+    }
+
     pub fn reset(&mut self) {
         if let Some(cx) = self.tmpcx.take() {
             self.cx = cx;
@@ -103,8 +161,9 @@ impl<'a> Lexer<'a> {
     }
 
     fn lx_tok(&mut self) -> Result<Token<'a>, Trace<'a, LxErr>> {
-        self.skip_ws();
-        Ok(match self.peek().or(Some(b'\0')).unwrap() {
+        self.skip_comments()?;
+        Ok(match self.peek().unwrap_or(b'\0') {
+            b'r' if self.peek_n(1) == Some(b'#') => self.lx_raw_str()?,
             b'a'..=b'z' | b'A'..=b'Z' | b'_' => self.lx_ident()?,
             b'0'..=b'9' => self.lx_num()?,
             b'!' => match self.peek_n(1) {
@@ -130,7 +189,7 @@ impl<'a> Lexer<'a> {
                     self.take();
                     Token {
                         src: self.src_double(),
-                        kind: TokKind::Eq,
+                        kind: TokKind::DoubleEq,
                     }
                 }
                 _ => {
@@ -141,6 +200,30 @@ impl<'a> Lexer<'a> {
                     }
                 }
             },
+            b'<' | b'>' => {
+                let ch = self.take().unwrap();
+                match (ch, self.peek()) {
+                    (b'<', Some(b'=')) | (b'>', Some(b'=')) => {
+                        self.take();
+                        Token {
+                            src: self.src_double(),
+                            kind: if ch == b'<' { TokKind::Le } else { TokKind::Ge },
+                        }
+                    }
+                    _ => Token {
+                        src: self.src_single(),
+                        kind: TokKind::from(ch),
+                    },
+                }
+            }
+            b':' | b'&' | b'|' if self.peek_n(1) == self.peek() => {
+                self.take();
+                self.take();
+                Token {
+                    src: self.src_double(),
+                    kind: TokKind::from(self.try_to_str(&self.src[self.cx.ix - 2..self.cx.ix])?),
+                }
+            }
             b'{' => match self.peek_n(1) {
                 Some(b'{') => {
                     self.take();
@@ -186,25 +269,20 @@ impl<'a> Lexer<'a> {
                         kind: TokKind::RCurly,
                     }
                 }
-            }
+            },
             b'"' => {
                 self.take();
                 let src = self.src_single();
 
                 let string = self.lx_str(true)?;
                 self.cx.pending.push(string);
-
-                self.take();
-                self.cx.pending.push(Token {
-                    src: self.src_single(),
-                    kind: TokKind::CloserDQuote,
-                });
+                self.lx_dq_closer();
 
                 Token {
                     src,
                     kind: TokKind::OpenerDQuote,
                 }
-            },
+            }
             b'$' => match self.peek_n(1) {
                 Some(b'}') => {
                     self.take();
@@ -226,7 +304,7 @@ impl<'a> Lexer<'a> {
             ch => {
                 self.take();
                 Token {
-                    src: if self.src.len() == 0 {
+                    src: if self.src.is_empty() {
                         self.src(&self.src[self.cx.ix..])
                     } else {
                         self.src_single()
@@ -242,25 +320,51 @@ impl<'a> Lexer<'a> {
 
         while let Some(ch) = self.peek() {
             let token_kind = match ch {
+                // Escapes are resolved by the parser, here they only
+                // keep the next character from ending the string.
+                b'\\' => {
+                    self.take();
+                    match self.peek() {
+                        Some(_) if raw_str => {
+                            self.take();
+                        }
+                        Some(b'{' | b'}' | b'@') => {
+                            self.take();
+                        }
+                        _ => {}
+                    }
+                    continue;
+                }
                 b'"' if raw_str => Some(TokKind::StringLit),
-                b'}' => match self.peek_n(1) {
-                    Some(b'}') => Some(TokKind::StringLit),
+                // In a run of `}`, an odd one out belongs to the
+                // string so `{{fn main() {}}}` closes at the end.
+                b'}' if !raw_str => match self.peek_n(1) {
+                    Some(b'}') => {
+                        let run = self.src[self.cx.ix..]
+                            .iter()
+                            .take_while(|ch| **ch == b'}')
+                            .count();
+                        (run % 2 == 0).then_some(TokKind::StringLit)
+                    }
                     _ => None,
                 },
                 b'{' => match self.peek_n(1) {
                     Some(b'$') => Some(TokKind::StringLit),
                     _ => None,
                 },
-                b'@' => {
+                b'@' if !raw_str => {
+                    let cx = self.cx.clone();
                     self.take();
                     let next = self.lx_ident()?;
                     tilog::info!(lex, "Next: `{}`", next.val());
                     match next.kind {
-                        TokKind::For 
-                        | TokKind::If 
-                        | TokKind::Else 
-                        | TokKind::ElseIf => Some(next.kind),
-                        _ => None
+                        TokKind::For | TokKind::If | TokKind::Else | TokKind::ElseIf => {
+                            // Leave the `@` in place so the parser
+                            // can pick up the control flow keyword.
+                            self.cx = cx;
+                            Some(TokKind::StringLit)
+                        }
+                        _ => continue,
                     }
                 }
                 _ => None,
@@ -268,7 +372,7 @@ impl<'a> Lexer<'a> {
             let buf = &self.src[six..self.cx.ix];
             if let Some(kind) = token_kind {
                 return Ok(Token {
-                    src: self.src(&buf),
+                    src: self.src(buf),
                     kind,
                 });
             }
@@ -278,14 +382,75 @@ impl<'a> Lexer<'a> {
         Err(self.err(LxErrKind::UnterminatedString, six))
     }
 
+    /// Lexes `r#{{ .. }}#`, the body ends at the first `}}` followed
+    /// by as many `#` as it was opened with, so `r##{{ }}# }}##` works.
+    fn lx_raw_str(&mut self) -> Result<Token<'a>, Trace<'a, LxErr>> {
+        let six = self.cx.ix;
+        self.take();
+        let mut closer = b"}}".to_vec();
+        while let Some(b'#') = self.peek() {
+            self.take();
+            closer.push(b'#');
+        }
+        if self.peek() != Some(b'{') || self.peek_n(1) != Some(b'{') {
+            return Err(self.err(LxErrKind::InvalidToken, six));
+        }
+        self.take();
+        self.take();
+
+        let bix = self.cx.ix;
+        while self.peek().is_some() {
+            if self.src[self.cx.ix..].starts_with(&closer) {
+                let buf = &self.src[bix..self.cx.ix];
+                let _ = self.try_to_str(buf)?;
+                let src = self.src(buf);
+                for _ in 0..closer.len() {
+                    self.take();
+                }
+                return Ok(Token {
+                    src,
+                    kind: TokKind::RawStringLit,
+                });
+            }
+            self.take();
+        }
+
+        Err(self.err(LxErrKind::UnterminatedString, six))
+    }
+
+    /// Resolves the escapes of a string token. In `"` strings these
+    /// are `\"`, `\\`, `\n`, `\t` and `\{`, in `{{ .. }}` bodies only
+    /// `\{`, `\}` and `\@` so code such as `"\n"` is left alone.
+    pub fn unescape(s: &str, dq: bool) -> String {
+        let mut buf = String::with_capacity(s.len());
+        let mut chars = s.chars();
+        while let Some(ch) = chars.next() {
+            if ch != '\\' {
+                buf.push(ch);
+                continue;
+            }
+            match chars.next() {
+                Some('n') if dq => buf.push('\n'),
+                Some('t') if dq => buf.push('\t'),
+                Some(ch @ ('"' | '\\')) if dq => buf.push(ch),
+                Some(ch @ ('{' | '}' | '@' | '$')) => buf.push(ch),
+                Some(ch) => {
+                    buf.push('\\');
+                    buf.push(ch);
+                }
+                None => buf.push('\\'),
+            }
+        }
+        buf
+    }
 
     #[rustfmt::skip]
     fn lx_ident(&mut self) -> Result<Token<'a>, Trace<'a, LxErr>> {
         let six = self.cx.ix;
-        let is_num = |ch| b'0' <= ch && ch <= b'9' 
+        let is_num = |ch: u8| ch.is_ascii_digit() 
             || ch == b'.';
-        let is_ident = |ch| b'a' <= ch && ch <= b'z' 
-            || b'A' <= ch && ch <= b'Z' 
+        let is_ident = |ch: u8| ch.is_ascii_lowercase() 
+            || ch.is_ascii_uppercase() 
             || ch == b'_' 
             || ch == b'.';
 
@@ -306,7 +471,7 @@ impl<'a> Lexer<'a> {
         let val = self.try_to_str(buf)?;
 
         Ok(Token::<'a> {
-            src: self.src(&buf),
+            src: self.src(buf),
             kind: TokKind::from(val),
         })
     }
@@ -314,7 +479,7 @@ impl<'a> Lexer<'a> {
     fn lx_num(&mut self) -> Result<Token<'a>, Trace<'a, LxErr>> {
         let six = self.cx.ix;
         while let Some(ch) = self.peek() {
-            if !(b'.' <= ch && ch <= b'9') {
+            if !ch.is_ascii_digit() {
                 break;
             }
             self.take();
@@ -322,7 +487,7 @@ impl<'a> Lexer<'a> {
 
         let buf = &self.src[six..self.cx.ix];
         Ok(Token {
-            src: self.src(&buf),
+            src: self.src(buf),
             kind: TokKind::IntLit,
         })
     }
@@ -363,6 +528,7 @@ impl<'a> Lexer<'a> {
                 LxErrKind::InvalidUtf8 => LxErr::InvalidUtf8(src_str),
                 LxErrKind::UnexpectedEOF => LxErr::UnexpectedEOF(src_str),
                 LxErrKind::UnterminatedString => LxErr::Unterminated(src_str),
+                LxErrKind::UnterminatedComment => LxErr::UnterminatedComment(src_str),
             },
         }
     }
@@ -370,9 +536,10 @@ impl<'a> Lexer<'a> {
     fn src(&self, buf: &'a [u8]) -> Source<'a> {
         Source {
             bix: self.cx.ix - buf.len(),
-            col: self.cx.col - buf.len(),
+            // Multi-line tokens (string bodies) start on an earlier line.
+            col: self.cx.col.saturating_sub(buf.len()),
             line: self.cx.line,
-            buf: &buf,
+            buf,
             len: buf.len(),
         }
     }
@@ -401,6 +568,50 @@ impl<'a> Lexer<'a> {
         std::str::from_utf8(buf).map_err(|_| self.err(LxErrKind::InvalidUtf8, self.cx.ix))
     }
 
+    /// Skips whitespace, `//` and `/* */` comments, collecting the
+    /// lines of `///` doc comments.
+    fn skip_comments(&mut self) -> Result<(), Trace<'a, LxErr>> {
+        self.cx.doc.clear();
+        loop {
+            self.skip_ws();
+            match (self.peek(), self.peek_n(1)) {
+                (Some(b'/'), Some(b'/')) => {
+                    let six = self.cx.ix;
+                    while self.peek().is_some_and(|ch| !is_vert_ws(ch)) {
+                        self.take();
+                    }
+                    let line = self.try_to_str(&self.src[six..self.cx.ix])?;
+                    if let Some(doc) = line.strip_prefix("///").filter(|d| !d.starts_with('/')) {
+                        self.cx
+                            .doc
+                            .push(doc.strip_prefix(' ').unwrap_or(doc).trim_end());
+                    }
+                }
+                (Some(b'/'), Some(b'*')) => {
+                    let six = self.cx.ix;
+                    let start = self.cx.clone();
+                    self.take();
+                    self.take();
+                    loop {
+                        match self.take() {
+                            Some(b'*') if self.peek() == Some(b'/') => break,
+                            Some(_) => {}
+                            None => {
+                                // Reported at the opener, not at the end of the input.
+                                self.cx = start;
+                                self.take();
+                                self.take();
+                                return Err(self.err(LxErrKind::UnterminatedComment, six));
+                            }
+                        }
+                    }
+                    self.take();
+                }
+                _ => return Ok(()),
+            }
+        }
+    }
+
     fn skip_ws(&mut self) {
         while let Some(ch) = self.peek() {
             if !is_ws(ch) {
@@ -415,6 +626,33 @@ impl<'a> Lexer<'a> {
 mod lexer_tests {
     use super::*;
 
+    #[test]
+    fn test_comments() {
+        let src = "// a\n/* b\n c */ /// doc\n///  two\nlet x = a / b; //// not doc\n";
+        let mut lx = Lexer::new(src.as_bytes());
+        let tok = lx.next_token().unwrap();
+        assert_eq!(tok.kind, TokKind::Let);
+        assert_eq!(lx.take_doc(), Some("doc\n two".to_string()));
+        let kinds = lx.lex().unwrap().iter().map(|t| t.kind).collect::<Vec<_>>();
+        assert_eq!(
+            kinds,
+            vec![
+                TokKind::Ident,
+                TokKind::Eq,
+                TokKind::Ident,
+                TokKind::Slash,
+                TokKind::Ident,
+                TokKind::Semi
+            ]
+        );
+        assert_eq!(lx.take_doc(), None);
+
+        let mut lx = Lexer::new(b"x\n  /* open\n more");
+        assert!(lx.next_token().is_ok());
+        let err = lx.next_token().unwrap_err();
+        assert_eq!(err.err, LxErr::UnterminatedComment("1:3 - [/*]".into()));
+    }
+
     #[test]
     fn test_symbols() {
         let src = "!@#$()[]{},.;:=";
@@ -430,7 +668,7 @@ mod lexer_tests {
     fn test_idents() {
         let src =
             "abc def ghi jkl mno pqr stu vwx yz ABC DEF GHI JKL MNO PQR STU VWX YZ _ _a _0 _a0";
-        let inp = src.split(' ').into_iter().collect::<Vec<&str>>();
+        let inp = src.split(' ').collect::<Vec<&str>>();
         let mut lx = Lexer::new(src.as_bytes());
         let toks = lx.lex().unwrap();
         println!("{:#?}", toks);
@@ -443,7 +681,7 @@ mod lexer_tests {
     #[test]
     fn test_keywords() {
         let src = "main let dir file req for in if else elseif str list";
-        let inp = src.split(' ').into_iter().collect::<Vec<&str>>();
+        let inp = src.split(' ').collect::<Vec<&str>>();
         let mut lx = Lexer::new(src.as_bytes());
         let toks = lx.lex().unwrap();
         println!("{:#?}", toks);
@@ -452,5 +690,17 @@ mod lexer_tests {
             assert!(tok.val() == inp[i], "[{}]", tok);
         }
     }
-    
+
+    #[test]
+    fn test_operators() {
+        let src = "+ - * / == != < > <= >= && || ! |";
+        let inp = src.split(' ').collect::<Vec<&str>>();
+        let mut lx = Lexer::new(src.as_bytes());
+        let toks = lx.lex().unwrap();
+        assert_eq!(toks.len(), inp.len());
+        for (i, tok) in toks.iter().enumerate() {
+            assert!(tok.val() == inp[i], "[{}]", tok);
+            assert_eq!(tok.kind, TokKind::from(inp[i]), "[{}]", tok);
+        }
+    }
 }
@@ -7,6 +7,7 @@ pub mod consts;
 pub mod err;
 pub mod exec;
 pub mod fifo;
+pub mod indent;
 pub mod lexer;
 pub mod smallvec;
 pub mod stack;
@@ -28,13 +29,22 @@ pub struct Template {
     pub body: String,
 }
 
+/// Parses `name=value` pairs, a name given more than
+/// once collects its values into a list.
 pub fn args(args: Vec<String>) -> Option<Vec<(String, Expr)>> {
-    let mut res = Vec::new();
+    let mut res: Vec<(String, Lit)> = Vec::new();
     for arg in args {
-        let mut arg = arg.split('=');
-        let name = arg.next().unwrap().to_string();
-        let val = Expr::Lit(Lit::String(arg.next().unwrap().to_string()));
-        res.push((name, val));
+        let (name, val) = arg.split_once('=')?;
+        let val = Lit::String(val.to_string());
+        match res.iter_mut().find(|(n, _)| n == name) {
+            Some((_, Lit::List(items))) => items.push(val),
+            Some((_, prev)) => *prev = Lit::List(vec![prev.clone(), val]),
+            None => res.push((name.to_string(), val)),
+        }
     }
-    Some(res)
+    Some(
+        res.into_iter()
+            .map(|(name, val)| (name, Expr::Lit(val)))
+            .collect(),
+    )
 }
@@ -1,5 +1,6 @@
 use clap::Parser;
 use tic::actions::local::Local;
+use tic::ast::OnConflict;
 
 #[derive(clap::Parser)]
 struct App {
@@ -36,6 +37,15 @@ enum Cmd {
 
         #[clap(short, long, num_args(0..))]
         args: Vec<String>,
+
+        #[clap(long, help = "Print what would be generated without writing it")]
+        dry_run: bool,
+
+        #[clap(long, help = "fail, skip, overwrite, prompt or backup [default: fail]")]
+        on_conflict: Option<OnConflict>,
+
+        #[clap(long, short, help = "Dir to generate into, created if missing")]
+        out: Option<std::path::PathBuf>,
     },
     #[clap(name = "pub", alias = "p", about = "Publish a template")]
     Pub {
@@ -73,13 +83,22 @@ fn main() -> std::io::Result<()> {
         Cmd::Repl => {
             tic::repl();
         }
-        Cmd::Make { name, args } => {
+        Cmd::Make {
+            name,
+            args,
+            dry_run,
+            on_conflict,
+            out,
+        } => {
             let program_args = tic::args(args);
-            if let None = program_args {
+            if program_args.is_none() {
                 println!("Error: Invalid arguments");
-                return Ok(());
+                std::process::exit(1);
+            }
+            if let Err(err) = tic::make(name, program_args.unwrap(), dry_run, on_conflict, out) {
+                println!("{}", err);
+                std::process::exit(1);
             }
-            tic::make(name, program_args.unwrap());
         }
         Cmd::Pub {
             name, path, force, ..
@@ -49,6 +49,15 @@ where
     }
 }
 
+impl<T, const N: usize> Default for SmallVec<T, N>
+where
+    T: Sized + Copy,
+{
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<T, const N: usize> SmallVec<T, N>
 where
     T: Sized + Copy,
@@ -106,7 +115,7 @@ where
         if self.is_heap {
             result = unsafe { std::ptr::read(self.storage.heap.as_ptr().add(index)) };
             unsafe {
-                (&mut self.storage.heap).remove(index);
+                (*self.storage.heap).remove(index);
             }
         } else {
             result = unsafe { std::ptr::read(self.storage.inline.as_ptr().add(index).cast::<T>()) };
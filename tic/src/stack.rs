@@ -3,6 +3,12 @@ pub struct Stack<const N: usize, E> {
     stack: Vec<E>,
 }
 
+impl<const N: usize, E: Copy> Default for Stack<N, E> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl<const N: usize, E: Copy> Stack<N, E> {
     pub fn new() -> Self {
         Self {
@@ -1,14 +1,41 @@
 use crate::{
-    ast::{Ast, Dir, Expr, File, If, Let, Lit, Ref, Ty, BinOp, Op},
+    ast::{
+        Annot, Ast, BinOp, Cfg, Dir, Eol, Expr, File, For, If, Let, Link, Lit, OnConflict, Op,
+        Param, Pipe, Ref, Req, Ty, UnOp, Use, UseFrom,
+    },
     err::{SynErr, Trace},
+    exec::dump::dump_path,
+    indent,
     lexer::Lexer,
     stack::Stack,
     token::{Closer, Opener, TokKind, Token},
 };
 
+type BodyFn<'a> = fn(&mut Syntax<'a>) -> Result<Vec<Expr>, Trace<'a, SynErr>>;
+
+fn binop(kind: TokKind) -> Option<Op> {
+    Some(match kind {
+        TokKind::Plus => Op::Add,
+        TokKind::Minus => Op::Sub,
+        TokKind::Star => Op::Mul,
+        TokKind::Slash => Op::Div,
+        TokKind::DoubleEq => Op::Eq,
+        TokKind::Neq => Op::Neq,
+        TokKind::Lt => Op::Lt,
+        TokKind::Gt => Op::Gt,
+        TokKind::Le => Op::Le,
+        TokKind::Ge => Op::Ge,
+        TokKind::And => Op::And,
+        TokKind::Or => Op::Or,
+        _ => return None,
+    })
+}
+
 pub struct Syntax<'a> {
     lx: Lexer<'a>,
     _errs: Vec<Trace<'a, SynErr>>,
+    /// Set by `[dedent=..]` in the `cfg` block.
+    dedent: bool,
 }
 
 impl<'a> Syntax<'a> {
@@ -16,6 +43,7 @@ impl<'a> Syntax<'a> {
         Self {
             lx: Lexer::new(src),
             _errs: Vec::new(),
+            dedent: true,
         }
     }
 
@@ -44,29 +72,44 @@ impl<'a> Syntax<'a> {
 
     pub fn parse(&mut self) -> Result<Ast, Trace<'a, SynErr>> {
         let tok = self.take()?;
-        match tok.kind {
+        let doc = self.lx.take_doc();
+        let mut ast = match tok.kind {
             TokKind::Main => {
                 let mut ast = self.parse()?;
                 ast.set_main();
-                Ok(ast)
+                ast
+            }
+            TokKind::Dir => Ast::Dir(self.parse_dir_lit()?),
+            TokKind::Let => Ast::Let(self.parse_let()?),
+            TokKind::File => Ast::File(self.parse_file_lit()?),
+            TokKind::Req => Ast::Req(self.parse_req()?),
+            TokKind::Use => Ast::Use(self.parse_use()?),
+            TokKind::Cfg => Ast::Cfg(self.parse_cfg()?),
+            TokKind::LSquare => {
+                let annots = self.parse_annots()?;
+                let mut ast = self.parse()?;
+                if !ast.set_annots(annots) {
+                    return Err(Trace::new_syn(tok, "let, file or dir after annotations"));
+                }
+                ast
             }
-            TokKind::Dir => Ok(Ast::Dir(self.parse_dir_lit()?)),
-            TokKind::Let => Ok(Ast::Let(self.parse_let()?)),
-            TokKind::File => Ok(Ast::File(self.parse_file_lit()?)),
-            TokKind::Req => Ok(Ast::Ref(self.parse_req()?)),
 
             _ => {
                 let tok = self.lx.look_ahead()?;
-                Err(Trace::new(
+                return Err(Trace::new(
                     tok.src,
                     SynErr::Expected(
                         "struct or file".into(),
                         tok.kind.to_string(),
                         tok.src.to_string(),
                     ),
-                ))
+                ));
             }
+        };
+        if let Some(doc) = doc {
+            ast.set_doc(doc);
         }
+        Ok(ast)
     }
 
     pub fn parse_dir_lit(&mut self) -> Result<Dir, Trace<'a, SynErr>> {
@@ -76,11 +119,11 @@ impl<'a> Syntax<'a> {
                 TokKind::OpenerDQuote => {
                     let v = self.assert(TokKind::StringLit)?.val_owned();
                     let _ = self.assert(TokKind::CloserDQuote);
-                    (v.clone(), Some(v))
+                    (v.clone(), Some(vec![Expr::Lit(Lit::String(v))]))
                 }
                 TokKind::Ident => {
                     let mut buf = tok.val_owned();
-                    while let Ok(_) = self.consume_if(TokKind::Slash) {
+                    while self.consume_if(TokKind::Slash).is_ok() {
                         buf.push('/');
                         buf.push_str(self.assert(TokKind::Ident)?.val());
                     }
@@ -92,162 +135,278 @@ impl<'a> Syntax<'a> {
 
         let params = self.parse_params()?;
         if path.is_none() {
-            path = if let Ok(_) = self.consume_if(TokKind::Colon) {
-                if let TokKind::OpenerDQuote = self.look_ahead_one()?.kind {
-                    Some(self.parse_raw_string()?)
-                } else {
-                    let mut buf = self.assert(TokKind::Ident)?.val().to_owned();
-                    while let Ok(_) = self.consume_if(TokKind::Slash) {
-                        buf.push('/');
-                        buf.push_str(self.assert(TokKind::Ident)?.val());
-                    }
-                    Some(buf)
-                }
+            path = if self.consume_if(TokKind::Colon).is_ok() {
+                Some(self.parse_path()?)
             } else {
-                Some(ident.clone())
+                Some(vec![Expr::Lit(Lit::String(ident.clone()))])
             }
         };
 
+        let mut lets = Vec::new();
+        let children = self.parse_dir_children(Some(&mut lets))?;
         let di = Dir {
             main: false,
-            path: std::path::PathBuf::from(path.unwrap()),
+            doc: None,
+            annots: Vec::new(),
+            path: path.unwrap(),
             alias: ident,
             params,
-            children: self.parse_children()?,
+            lets,
+            scope: None,
+            children,
         };
 
         Ok(di)
     }
 
     pub fn parse_children(&mut self) -> Result<Vec<Expr>, Trace<'a, SynErr>> {
-        if let Ok(_) = self.consume_if(TokKind::Semi) {
+        self.parse_dir_children(None)
+    }
+
+    /// Parses the body of a dir, `lets` collects the `let`s declared
+    /// in it and is `None` for `@if` and `@for` bodies.
+    fn parse_dir_children(
+        &mut self,
+        mut lets: Option<&mut Vec<Let>>,
+    ) -> Result<Vec<Expr>, Trace<'a, SynErr>> {
+        if self.consume_if(TokKind::Semi).is_ok() {
             return Ok(Vec::new());
         }
 
         let _ = self.assert(TokKind::LCurly)?;
-        if let Ok(_) = self.consume_if(TokKind::RCurly) {
-            return Ok(Vec::new());
-        }
 
         let mut children = Vec::new();
-        while let Ok(tok) = self.consume_if_union(&[
-            TokKind::At,
-            TokKind::Comma,
-            TokKind::OpenerDQuote,
-            TokKind::Ident,
-        ]) {
-            println!("TOK: {:?}", tok);
+        let mut annots = Vec::new();
+        loop {
+            let tok = self.look_ahead_one()?;
+            tilog::debug!(parse, "Child: {}", tok);
+            let named = matches!(
+                tok.kind,
+                TokKind::OpenerDQuote | TokKind::Ident | TokKind::LCurlyDollar
+            );
+            if !annots.is_empty() && !named {
+                return Err(Trace::new_syn(tok, "file or dir after annotations"));
+            }
             match tok.kind {
-                TokKind::Comma => {}
-                TokKind::At => {
-                    children.push(Expr::Ref(Ref {
-                        name: self.assert(TokKind::Ident)?.val_owned(),
-                        args: self.parse_args()?,
-                        ty: Ty::Unknown,
-                    }));
-                    if let Ok(_) = self.consume_if(TokKind::Comma) {
-                        if let Ok(_) = self.consume_if(TokKind::RCurly) {
-                            break;
-                        }
-                        continue;
+                TokKind::LSquare => {
+                    let _ = self.take()?;
+                    annots = self.parse_annots()?;
+                }
+                TokKind::RCurly => {
+                    let _ = self.take()?;
+                    break;
+                }
+                TokKind::Comma => {
+                    let _ = self.take()?;
+                }
+                TokKind::Let => match lets {
+                    Some(ref mut lets) => {
+                        let _ = self.take()?;
+                        lets.push(self.parse_let()?);
                     }
-                    self.assert(TokKind::RCurly)?;
+                    None => {
+                        return Err(Trace::new_syn(
+                            tok,
+                            "file or dir, `let` is not allowed here",
+                        ))
+                    }
+                },
+                TokKind::At => {
+                    let _ = self.take()?;
+                    let tok = self.assert_union(&[TokKind::Ident, TokKind::If, TokKind::For])?;
+                    children.push(match tok.kind {
+                        TokKind::If => Expr::If(self.parse_if(Self::parse_children)?),
+                        TokKind::For => Expr::For(self.parse_for(Self::parse_children)?),
+                        _ => Expr::Ref(Ref {
+                            name: self.parse_ref_name(tok)?,
+                            args: self.parse_args()?,
+                            ty: Ty::Unknown,
+                        }),
+                    });
                 }
                 TokKind::OpenerDQuote => {
-                    let name = self.assert(TokKind::StringLit)?.val();
-                    let _ = self.assert(TokKind::CloserDQuote)?;
-                    if let Ok(_) = self.consume_if(TokKind::Colon) {
-                        let content = if let Ok(ident) = self.consume_if(TokKind::Ident) {
-                            vec![Expr::Ref(Ref {
-                                name: ident.val_owned(),
-                                args: self.parse_args()?,
-                                ty: Ty::String,
-                            })]
-                        } else {
-                            self.parse_string()?
-                        };
-                        children.push(Expr::Lit(Lit::File(File {
-                            main: false,
-                            params: Vec::new(),
-                            path: name.to_owned(),
-                            alias: name.to_owned(),
-                            content,
-                        })))
-                    } else {
-                        children.push(Expr::Lit(Lit::File(File {
-                            main: false,
-                            params: Vec::new(),
-                            path: name.to_owned(),
-                            alias: name.to_owned(),
-                            content: Vec::new(),
-                        })))
-                    }
-                    self.assert_union(&[TokKind::Comma, TokKind::RCurly])?;
+                    let name = self.parse_string()?;
+                    let annots = std::mem::take(&mut annots);
+                    children.push(self.parse_child(name, true, annots)?);
                 }
-                TokKind::Ident => {
-                    if let TokKind::LCurly = self.look_ahead_one()?.kind {
-                        children.push(Expr::Lit(Lit::Dir(Dir {
-                            main: false,
-                            params: Vec::new(),
-                            path: tok.val_owned().into(),
-                            alias: tok.val_owned(),
-                            children: self.parse_children()?,
-                        })));
-                        let _ = self.consume_if_union(&[TokKind::RCurly]);
-                        continue;
+                TokKind::Ident | TokKind::LCurlyDollar => {
+                    let name = self.parse_name()?;
+                    let annots = std::mem::take(&mut annots);
+                    children.push(self.parse_child(name, false, annots)?);
+                }
+                _ => {
+                    let _ = self.assert_union(&[
+                        TokKind::At,
+                        TokKind::LSquare,
+                        TokKind::Let,
+                        TokKind::Comma,
+                        TokKind::OpenerDQuote,
+                        TokKind::Ident,
+                        TokKind::LCurlyDollar,
+                        TokKind::RCurly,
+                    ])?;
+                    unreachable!()
+                }
+            }
+        }
+
+        Ok(children)
+    }
+
+    /// Parses whatever follows the name of a child inside a `dir` body.
+    ///
+    /// `name { .. }` is a directory and `name: ..` a file with content.
+    /// Otherwise a quoted name is an empty file and an ident an empty
+    /// directory.
+    fn parse_child(
+        &mut self,
+        path: Vec<Expr>,
+        quoted: bool,
+        annots: Vec<Annot>,
+    ) -> Result<Expr, Trace<'a, SynErr>> {
+        let alias = dump_path(&path);
+        let next = self.look_ahead_one()?;
+        Ok(match next.kind {
+            // `link` is only a keyword in front of a name, so
+            // a dir can still be called `link`.
+            TokKind::OpenerDQuote | TokKind::Ident | TokKind::LCurlyDollar
+                if !quoted && alias == "link" =>
+            {
+                let path = self.parse_path()?;
+                let _ = self.assert(TokKind::Colon)?;
+                Expr::Lit(Lit::Link(Link {
+                    annots,
+                    alias: dump_path(&path),
+                    path,
+                    target: self.parse_string()?,
+                }))
+            }
+            TokKind::LCurly => {
+                let mut lets = Vec::new();
+                let children = self.parse_dir_children(Some(&mut lets))?;
+                Expr::Lit(Lit::Dir(Dir {
+                    main: false,
+                    doc: None,
+                    annots,
+                    params: Vec::new(),
+                    path,
+                    alias,
+                    lets,
+                    scope: None,
+                    children,
+                }))
+            }
+            TokKind::Colon => {
+                let _ = self.take()?;
+                let content = match self.consume_if_union(&[TokKind::At, TokKind::Ident]) {
+                    Ok(tok) => {
+                        let ident = match tok.kind {
+                            TokKind::At => self.assert(TokKind::Ident)?,
+                            _ => tok,
+                        };
+                        vec![Expr::Ref(Ref {
+                            name: self.parse_ref_name(ident)?,
+                            args: self.parse_args()?,
+                            ty: Ty::String,
+                        })]
                     }
+                    Err(_) => self.parse_body()?,
+                };
+                Expr::Lit(Lit::File(File {
+                    main: false,
+                    doc: None,
+                    annots,
+                    params: Vec::new(),
+                    path,
+                    alias,
+                    content,
+                }))
+            }
+            _ if quoted => Expr::Lit(Lit::File(File {
+                main: false,
+                doc: None,
+                annots,
+                params: Vec::new(),
+                path,
+                alias,
+                content: Vec::new(),
+            })),
+            _ => Expr::Lit(Lit::Dir(Dir {
+                main: false,
+                doc: None,
+                annots,
+                params: Vec::new(),
+                path,
+                alias,
+                lets: Vec::new(),
+                scope: None,
+                children: Vec::new(),
+            })),
+        })
+    }
 
-                    let next =
-                        self.assert_union(&[TokKind::Comma, TokKind::Colon, TokKind::RCurly])?;
-
-                    match next.kind {
-                        TokKind::Comma => {
-                            children.push(Expr::Lit(Lit::Dir(Dir {
-                                main: false,
-                                params: Vec::new(),
-                                path: tok.val_owned().into(),
-                                alias: tok.val_owned(),
-                                children: self.parse_children()?,
-                            })));
-                        }
-                        TokKind::Colon => {
-                            if let Token {
-                                kind: TokKind::Ident,
-                                ..
-                            } = self.look_ahead_one()?
-                            {
-                                children.push(Expr::Lit(Lit::File(File {
-                                    main: false,
-                                    params: Vec::new(),
-                                    path: tok.val_owned().into(),
-                                    alias: tok.val_owned(),
-                                    content: vec![Expr::Ref(Ref {
-                                        name: self.assert(TokKind::Ident)?.val_owned(),
-                                        args: self.parse_args()?,
-                                        ty: Ty::String,
-                                    })],
-                                })))
-                            } else {
-                                children.push(Expr::Lit(Lit::File(File {
-                                    main: false,
-                                    params: Vec::new(),
-                                    path: tok.val_owned().into(),
-                                    alias: tok.val_owned(),
-                                    content: self.parse_string()?,
-                                })))
-                            }
-                        }
-                        TokKind::RCurly => {
-                            break;
-                        }
-                        _ => unreachable!(),
+    /// Parses the path after the `:` of a declaration, either a
+    /// string, `!ident` for the value of a symbol or an unquoted name.
+    fn parse_path(&mut self) -> Result<Vec<Expr>, Trace<'a, SynErr>> {
+        match self.look_ahead_one()?.kind {
+            TokKind::OpenerDQuote => self.parse_string(),
+            TokKind::Bang => {
+                let _ = self.take()?;
+                Ok(vec![Expr::Ref(Ref {
+                    name: {
+                        let tok = self.assert(TokKind::Ident)?;
+                        self.parse_ref_name(tok)?
+                    },
+                    args: Vec::new(),
+                    ty: Ty::String,
+                })])
+            }
+            _ => self.parse_name(),
+        }
+    }
+
+    /// Parses an unquoted name such as `src/{$ module $}/mod.rs`.
+    fn parse_name(&mut self) -> Result<Vec<Expr>, Trace<'a, SynErr>> {
+        let mut path = Vec::new();
+        let mut buf = String::new();
+        // Idents only continue a name after a separator, so that
+        // two children without a comma are not glued together.
+        let mut sep = true;
+        loop {
+            match self.look_ahead_one()?.kind {
+                TokKind::Ident if sep => {
+                    buf.push_str(self.take()?.val());
+                    sep = false;
+                }
+                TokKind::Slash | TokKind::Dot => {
+                    buf.push_str(self.take()?.val());
+                    sep = true;
+                }
+                TokKind::LCurlyDollar => {
+                    let _ = self.take()?;
+                    if !buf.is_empty() {
+                        path.push(Expr::Lit(Lit::String(std::mem::take(&mut buf))));
                     }
+                    path.push(match self.parse_expr()? {
+                        Expr::Ref(r) => Expr::Ref(Ref {
+                            ty: Ty::String,
+                            ..r
+                        }),
+                        expr => expr,
+                    });
+                    let _ = self.assert(TokKind::RCurlyDollar)?;
+                    sep = false;
                 }
-                _ => unreachable!(),
+                _ => break,
             }
         }
-
-        Ok(children)
+        if !buf.is_empty() {
+            path.push(Expr::Lit(Lit::String(buf)));
+        }
+        if path.is_empty() {
+            let _ = self.assert(TokKind::Ident)?;
+        }
+        Ok(path)
     }
 
     pub fn parse_file_lit(&mut self) -> Result<File, Trace<'a, SynErr>> {
@@ -257,7 +416,7 @@ impl<'a> Syntax<'a> {
                 TokKind::OpenerDQuote => {
                     let v = self.assert(TokKind::StringLit)?.val_owned();
                     let _ = self.assert(TokKind::CloserDQuote);
-                    (v.clone(), Some(v))
+                    (v.clone(), Some(vec![Expr::Lit(Lit::String(v))]))
                 }
                 TokKind::Ident => (tok.val_owned(), None),
                 _ => unreachable!(),
@@ -266,23 +425,20 @@ impl<'a> Syntax<'a> {
 
         let params = self.parse_params()?;
         if path.is_none() {
-            path = if let Ok(Token {
-                kind: TokKind::Colon,
-                ..
-            }) = self.look_ahead_one()
-            {
-                let _ = self.take();
-                Some(self.parse_raw_string()?)
+            path = if self.consume_if(TokKind::Colon).is_ok() {
+                Some(self.parse_path()?)
             } else {
-                Some(ident.clone())
+                Some(vec![Expr::Lit(Lit::String(ident.clone()))])
             }
         };
 
         let fi = File {
             main: false,
+            doc: None,
+            annots: Vec::new(),
             path: path.unwrap(),
             alias: ident,
-            content: self.parse_string()?,
+            content: self.parse_body()?,
             params,
         };
 
@@ -295,32 +451,199 @@ impl<'a> Syntax<'a> {
         let name = self.assert(TokKind::Ident)?;
         let params = self.parse_params()?;
         let _ = self.assert(TokKind::Eq)?;
-        let expr = self.parse_string()?;
+        let (ty, expr) = match self.look_ahead_one()?.kind {
+            TokKind::LCurlyDouble
+            | TokKind::RawStringLit
+            | TokKind::LCurlyDollar
+            | TokKind::OpenerDQuote => (Ty::String, self.parse_body()?),
+            _ => (Ty::Unknown, vec![self.parse_expr()?]),
+        };
         let _ = self.assert_union(&[TokKind::Semi, TokKind::EOF])?;
 
         Ok(Let {
             main: false,
+            doc: None,
+            annots: Vec::new(),
             name: name.val_owned(),
             params,
-            ty: Ty::String,
+            ty,
             expr,
         })
     }
 
-    pub fn parse_req(&mut self) -> Result<Ref, Trace<'a, SynErr>> {
+    pub fn parse_req(&mut self) -> Result<Req, Trace<'a, SynErr>> {
         let name = self.assert(TokKind::Ident)?;
         let _ = self.assert(TokKind::Colon)?;
-        let ty: Ty = self.assert(TokKind::Ident)?.val().into();
+        let ty = self.parse_ty()?;
+        let optional = self.consume_if(TokKind::Question).is_ok();
+        let default = self.parse_default()?;
         let _ = self.assert(TokKind::Semi)?;
 
-        Ok(Ref {
+        Ok(Req {
+            doc: None,
             name: name.val_owned(),
-            args: vec![],
             ty,
+            optional,
+            default,
         })
     }
 
-    fn parse_params(&mut self) -> Result<Vec<(String, Ty)>, Trace<'a, SynErr>> {
+    pub fn parse_use(&mut self) -> Result<Use, Trace<'a, SynErr>> {
+        let tok = self.assert_union(&[TokKind::OpenerDQuote, TokKind::Ident])?;
+        let from = match tok.kind {
+            TokKind::OpenerDQuote => {
+                let path = self.assert(TokKind::StringLit)?.val_owned();
+                let _ = self.assert(TokKind::CloserDQuote)?;
+                UseFrom::Path(path)
+            }
+            _ if tok.val() == "template" => {
+                UseFrom::Template(self.assert(TokKind::Ident)?.val_owned())
+            }
+            _ => return Err(Trace::new_syn(tok, "\"<path>\" or template <name>")),
+        };
+        let ns = if self.consume_if(TokKind::As).is_ok() {
+            self.assert(TokKind::Ident)?.val_owned()
+        } else {
+            match from {
+                UseFrom::Path(ref path) => std::path::Path::new(path)
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .unwrap_or(path)
+                    .to_owned(),
+                UseFrom::Template(ref name) => name.clone(),
+            }
+        };
+        let _ = self.assert(TokKind::Semi)?;
+
+        Ok(Use { from, ns })
+    }
+
+    /// Parses `cfg { [key=value] .. }`, see `Cfg` for the keys.
+    pub fn parse_cfg(&mut self) -> Result<Cfg, Trace<'a, SynErr>> {
+        let _ = self.assert(TokKind::LCurly)?;
+        let mut cfg = Cfg::default();
+        loop {
+            let tok = self.assert_union(&[TokKind::LSquare, TokKind::Comma, TokKind::RCurly])?;
+            match tok.kind {
+                TokKind::RCurly => break,
+                TokKind::Comma => {}
+                _ => {
+                    let key = self.assert(TokKind::Ident)?;
+                    let _ = self.assert(TokKind::Eq)?;
+                    let (tok, val) = self.parse_bare_val()?;
+                    let invalid = |expected: &str| Trace::new_syn(tok, expected);
+                    match key.val() {
+                        "indent" => {
+                            let indent = if val == "tab" {
+                                Some("\t".to_owned())
+                            } else if let Some(n) = val.strip_suffix('s') {
+                                n.parse().ok().map(|n| " ".repeat(n))
+                            } else if let Some(n) = val.strip_suffix('t') {
+                                n.parse().ok().map(|n| "\t".repeat(n))
+                            } else {
+                                None
+                            };
+                            cfg.indent = Some(indent.ok_or_else(|| invalid("4s, 1t or tab"))?);
+                        }
+                        "eol" => {
+                            cfg.eol = Some(match val.as_str() {
+                                "lf" => Eol::Lf,
+                                "crlf" => Eol::Crlf,
+                                _ => return Err(invalid("lf or crlf")),
+                            })
+                        }
+                        "final_newline" => {
+                            cfg.final_newline =
+                                Some(val.parse().map_err(|_| invalid("true or false"))?)
+                        }
+                        "dedent" => {
+                            cfg.dedent = Some(val.parse().map_err(|_| invalid("true or false"))?)
+                        }
+                        "reindent" => {
+                            cfg.reindent = Some(val.parse().map_err(|_| invalid("true or false"))?)
+                        }
+                        "on_conflict" => {
+                            cfg.on_conflict =
+                                Some(val.parse::<OnConflict>().map_err(|exp| invalid(&exp))?)
+                        }
+                        "out" => cfg.out = Some(val.into()),
+                        _ => {
+                            return Err(Trace::new_syn(
+                                key,
+                                "indent, eol, final_newline, dedent, reindent, on_conflict or out",
+                            ))
+                        }
+                    }
+                    let _ = self.assert(TokKind::RSquare)?;
+                }
+            }
+        }
+        self.dedent = cfg.dedent.unwrap_or(self.dedent);
+        Ok(cfg)
+    }
+
+    /// Parses `key=value` annotations after the opening `[`, several
+    /// may share brackets (`[a=1, b=2]`) or follow each other.
+    pub fn parse_annots(&mut self) -> Result<Vec<Annot>, Trace<'a, SynErr>> {
+        let mut annots = Vec::new();
+        loop {
+            let key = self.assert(TokKind::Ident)?.val_owned();
+            let _ = self.assert(TokKind::Eq)?;
+            let val = match key.as_str() {
+                "skip_if" => self.parse_expr()?,
+                _ => Expr::Lit(Lit::String(self.parse_bare_val()?.1)),
+            };
+            annots.push(Annot { key, val });
+            if self.consume_if(TokKind::Comma).is_ok() {
+                continue;
+            }
+            let _ = self.assert(TokKind::RSquare)?;
+            if self.consume_if(TokKind::LSquare).is_err() {
+                break;
+            }
+        }
+        Ok(annots)
+    }
+
+    /// Parses a value up to the closing `]`, joining the tokens so
+    /// unquoted values such as `4s` or `../out` can be written.
+    fn parse_bare_val(&mut self) -> Result<(Token<'a>, String), Trace<'a, SynErr>> {
+        let first = self.look_ahead_one()?;
+        let mut val = String::new();
+        loop {
+            let tok = self.look_ahead_one()?;
+            match tok.kind {
+                TokKind::RSquare | TokKind::Comma => break,
+                TokKind::OpenerDQuote => {
+                    let _ = self.take()?;
+                    val.push_str(self.assert(TokKind::StringLit)?.val());
+                    let _ = self.assert(TokKind::CloserDQuote)?;
+                }
+                TokKind::Ident
+                | TokKind::IntLit
+                | TokKind::Dot
+                | TokKind::Slash
+                | TokKind::Minus => val.push_str(self.take()?.val()),
+                _ => {
+                    let _ = self.assert(TokKind::RSquare)?;
+                    unreachable!()
+                }
+            }
+        }
+        Ok((first, val))
+    }
+
+    /// Parses the rest of a possibly namespaced name, e.g. `base::license`.
+    fn parse_ref_name(&mut self, first: Token<'a>) -> Result<String, Trace<'a, SynErr>> {
+        let mut name = first.val_owned();
+        while self.consume_if(TokKind::DoubleColon).is_ok() {
+            name.push_str("::");
+            name.push_str(self.assert(TokKind::Ident)?.val());
+        }
+        Ok(name)
+    }
+
+    fn parse_params(&mut self) -> Result<Vec<Param>, Trace<'a, SynErr>> {
         if let TokKind::LParen = self.look_ahead_one()?.kind {
             let _ = self.assert(TokKind::LParen)?;
             let mut params = Vec::new();
@@ -330,11 +653,12 @@ impl<'a> Syntax<'a> {
                 match tok.kind {
                     TokKind::Ident => {
                         self.assert(TokKind::Colon)?;
-                        let ty: Ty = self
-                            .assert_union(&[TokKind::Ident, TokKind::StringKw, TokKind::ListKw])?
-                            .val()
-                            .into();
-                        params.push((tok.val_owned(), ty));
+                        let ty = self.parse_ty()?;
+                        params.push(Param {
+                            name: tok.val_owned(),
+                            ty,
+                            default: self.parse_default()?,
+                        });
                     }
                     TokKind::RParen => break,
                     _ => {}
@@ -346,43 +670,49 @@ impl<'a> Syntax<'a> {
         }
     }
 
+    /// Parses an optional `= <expr>` after a declared type.
+    fn parse_default(&mut self) -> Result<Option<Expr>, Trace<'a, SynErr>> {
+        if self.consume_if(TokKind::Eq).is_ok() {
+            Ok(Some(self.parse_expr()?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Parses a type, lists may name their element type, e.g. `list<str>`.
+    fn parse_ty(&mut self) -> Result<Ty, Trace<'a, SynErr>> {
+        let tok = self.assert_union(&[TokKind::Ident, TokKind::StringKw, TokKind::ListKw])?;
+        if tok.kind == TokKind::ListKw && self.consume_if(TokKind::Lt).is_ok() {
+            let elem = self.parse_ty()?;
+            let _ = self.assert(TokKind::Gt)?;
+            return Ok(Ty::List(Box::new(elem)));
+        }
+        Ok(tok.val().into())
+    }
+
+    /// Parses call arguments, named as `name: expr` or positional,
+    /// positional arguments are stored with an empty name.
     fn parse_args(&mut self) -> Result<Vec<(String, Expr)>, Trace<'a, SynErr>> {
         if let TokKind::LParen = self.look_ahead_one()?.kind {
             self.take()?;
             let mut args = Vec::new();
-            while let Ok(tok) =
-                self.assert_union(&[TokKind::Ident, TokKind::Comma, TokKind::RParen])
-            {
-                match tok.kind {
-                    TokKind::Ident => {
-                        self.assert(TokKind::Colon)?;
-                        let arg_val =
-                            self.assert_union(&[TokKind::Ident, TokKind::OpenerDQuote])?;
-                        match arg_val.kind {
-                            TokKind::Ident => {
-                                let arg_args = self.parse_args()?;
-                                args.push((
-                                    tok.val_owned(),
-                                    Expr::Ref(Ref {
-                                        name: arg_val.val_owned(),
-                                        args: arg_args,
-                                        ty: Ty::String,
-                                    }),
-                                ));
-                            }
-                            TokKind::OpenerDQuote => {
-                                let arg_string = self.assert(TokKind::StringLit)?;
-                                let _ = self.assert(TokKind::CloserDQuote);
-                                args.push((
-                                    tok.val_owned(),
-                                    Expr::Lit(Lit::String(arg_string.val_owned())),
-                                ));
-                            }
-                            _ => unreachable!(),
-                        }
+            loop {
+                match self.look_ahead_one()?.kind {
+                    TokKind::RParen => {
+                        self.take()?;
+                        break;
                     }
-                    TokKind::RParen => break,
-                    _ => {}
+                    TokKind::Comma => {
+                        self.take()?;
+                    }
+                    _ => match self.parse_expr()? {
+                        Expr::Ref(r)
+                            if r.args.is_empty() && self.consume_if(TokKind::Colon).is_ok() =>
+                        {
+                            args.push((r.name, self.parse_expr()?));
+                        }
+                        expr => args.push((String::new(), expr)),
+                    },
                 }
             }
             Ok(args)
@@ -391,6 +721,18 @@ impl<'a> Syntax<'a> {
         }
     }
 
+    /// Parses the content of a let or file, dedenting `{{ .. }}` bodies.
+    fn parse_body(&mut self) -> Result<Vec<Expr>, Trace<'a, SynErr>> {
+        // Raw bodies are kept verbatim.
+        let block = self.look_ahead_one()?.kind == TokKind::LCurlyDouble;
+        let body = self.parse_string()?;
+        Ok(if block && self.dedent {
+            indent::dedent(body)
+        } else {
+            body
+        })
+    }
+
     fn parse_string(&mut self) -> Result<Vec<Expr>, Trace<'a, SynErr>> {
         let mut buf = Vec::new();
         let mut openers = Stack::<16, Opener>::new();
@@ -401,31 +743,56 @@ impl<'a> Syntax<'a> {
             TokKind::RCurlyDouble,
             TokKind::RCurlyDollar,
             TokKind::CloserDQuote,
+            TokKind::RawStringLit,
+            TokKind::At,
         ]) {
             match tok.kind {
+                TokKind::RawStringLit => {
+                    buf.push(Expr::Lit(Lit::Raw(tok.val_owned())));
+                    if openers.is_empty() {
+                        break;
+                    }
+                }
+                TokKind::At => {
+                    if let Ok(Opener::LCurlyDouble) = openers.peek() {
+                        let kw = self.assert_union(&[TokKind::For, TokKind::If])?;
+                        buf.push(match kw.kind {
+                            TokKind::For => Expr::For(self.parse_for(Self::parse_string)?),
+                            TokKind::If => Expr::If(self.parse_if(Self::parse_string)?),
+                            _ => unreachable!(),
+                        });
+                        let string = self.lx.try_lx_str()?;
+                        buf.push(Expr::Lit(Lit::String(Lexer::unescape(string.val(), false))));
+                    } else {
+                        return Err(Trace::new_syn(tok, "control flow inside {{ .. }}"));
+                    }
+                }
                 TokKind::LCurlyDollar => {
-                    let ident = self.assert(TokKind::Ident)?;
-                    let args = self.parse_args()?;
                     openers.push(Opener::LCurlyDollar);
-                    buf.push(Expr::Ref(Ref {
-                        name: ident.val_owned(),
-                        args,
-                        ty: Ty::String,
-                    }));
+                    buf.push(match self.parse_expr()? {
+                        Expr::Ref(r) => Expr::Ref(Ref {
+                            ty: Ty::String,
+                            ..r
+                        }),
+                        expr => expr,
+                    });
                 }
                 TokKind::LCurlyDouble | TokKind::OpenerDQuote => {
                     let string = self.assert(TokKind::StringLit)?;
                     openers.push(Opener::from(tok.kind));
-                    buf.push(Expr::Lit(Lit::String(string.val_owned())));
+                    let dq = tok.kind == TokKind::OpenerDQuote;
+                    buf.push(Expr::Lit(Lit::String(Lexer::unescape(string.val(), dq))));
                 }
                 TokKind::RCurlyDollar => {
                     if let Ok(op) = openers.peek() {
                         if op.closer() == Closer::RCurlyDollar {
                             let _ = openers.pop();
-                            if let Ok(Opener::LCurlyDouble) = openers.peek() {
-                                let string = self.lx.try_lx_str()?;
-                                buf.push(Expr::Lit(Lit::String(string.val_owned())));
-                            }
+                            let (string, dq) = match openers.peek() {
+                                Ok(Opener::LCurlyDouble) => (self.lx.try_lx_str()?, false),
+                                Ok(Opener::DQuote) => (self.lx.try_lx_dq_str()?, true),
+                                _ => continue,
+                            };
+                            buf.push(Expr::Lit(Lit::String(Lexer::unescape(string.val(), dq))));
                         } else {
                             return Err(Trace::new_syn(tok, "unmatched opener: {{\""));
                         }
@@ -464,100 +831,133 @@ impl<'a> Syntax<'a> {
         Ok(buf)
     }
 
-    fn parse_raw_string(&mut self) -> Result<String, Trace<'a, SynErr>> {
-        let mut buf = String::new();
-        if let Ok(_) = self.assert(TokKind::OpenerDQuote) {
-            let string = self.assert(TokKind::StringLit)?;
-            buf.push_str(&string.val());
-            let _ = self.assert(TokKind::CloserDQuote)?;
-        }
-        Ok(buf)
+    /// Parses `<var> in <iter>` followed by the loop body,
+    /// which is either a string or the children of a `dir`.
+    fn parse_for(&mut self, body: BodyFn<'a>) -> Result<For, Trace<'a, SynErr>> {
+        let var = self.assert(TokKind::Ident)?.val_owned();
+        let _ = self.assert(TokKind::In)?;
+        let iter = self.parse_expr()?;
+        let body = body(self)?;
+        Ok(For {
+            var,
+            iter: Box::new(iter),
+            body,
+        })
     }
 
-    fn parse_if(&mut self) -> Result<If, Trace<'a, SynErr>> {
-        let cond = self.parse_binop()?;
-        let then = self.parse_string()?;
-        let els = if let Ok(_) = self.consume_if(TokKind::Else) {
-            self.parse_string()?
-        } else {
-            Vec::new()
+    /// Parses a condition followed by the then and else bodies,
+    /// which are either strings or the children of a `dir`.
+    fn parse_if(&mut self, body: BodyFn<'a>) -> Result<If, Trace<'a, SynErr>> {
+        let cond = self.parse_expr()?;
+        let then = body(self)?;
+        let els = match self.lx.try_lx_else() {
+            Some(Token {
+                kind: TokKind::ElseIf,
+                ..
+            }) => vec![Expr::If(self.parse_if(body)?)],
+            Some(_) => body(self)?,
+            None => Vec::new(),
         };
-        Ok(If { cond, then, els })
+        Ok(If {
+            cond: Box::new(cond),
+            then,
+            els,
+        })
     }
 
-    #[rustfmt::skip]
-    fn parse_binop(&mut self) -> Result<BinOp, Trace<'a, SynErr>> {
-        let lhs = match self.assert_union(&[
-            TokKind::Ident, 
-            TokKind::OpenerDQuote, 
-            TokKind::IntLit
-        ]) {
-            Ok(tok) => match tok.kind {
-                TokKind::Ident => {
-                    let args = self.parse_args()?;
-                    Expr::Ref(Ref {
-                        name: tok.val_owned(),
-                        args,
-                        ty: Ty::Unknown,
-                    })
-                }
-                TokKind::OpenerDQuote => {
-                    let string = self.assert(TokKind::StringLit)?;
-                    self.assert(TokKind::CloserDQuote)?;
-                    Expr::Lit(Lit::String(string.val_owned()))
-                }
-                TokKind::IntLit => Expr::Lit(Lit::Int(tok.val_owned().parse().unwrap())),
-                _ => unreachable!(),
-            },
-            Err(e) => return Err(e),
-        };
+    fn parse_expr(&mut self) -> Result<Expr, Trace<'a, SynErr>> {
+        let mut expr = self.parse_binop(0)?;
+        while self.consume_if(TokKind::Pipe).is_ok() {
+            let filter = self.assert(TokKind::Ident)?.val_owned();
+            expr = Expr::Lit(Lit::Pipe(Pipe {
+                val: Box::new(expr),
+                filter,
+                args: self.parse_args()?,
+            }));
+        }
+        Ok(expr)
+    }
 
-        let op = match self.assert_union(&[
-            TokKind::Plus,
-            TokKind::Star,
-            TokKind::Eq,
-            TokKind::Neq,
-        ]) {
-            Ok(tok) => match tok.kind {
-                TokKind::Plus => Op::Add,
-                TokKind::Star => Op::Mul,
-                TokKind::Eq => Op::Eq,
-                TokKind::Neq => Op::Neq,
-                _ => unreachable!(),
-            },
-            Err(e) => return Err(e),
-        };
+    /// Precedence climbing over the binary operators, only
+    /// operators binding at least as tight as `min` are taken.
+    fn parse_binop(&mut self, min: u8) -> Result<Expr, Trace<'a, SynErr>> {
+        let mut lhs = self.parse_operand()?;
+
+        loop {
+            let op = match binop(self.look_ahead_one()?.kind) {
+                Some(op) if op.prec() >= min => op,
+                _ => break,
+            };
+            let _ = self.take()?;
+            let rhs = self.parse_binop(op.prec() + 1)?;
+            lhs = Expr::Lit(Lit::BinOp(BinOp {
+                op,
+                lhs: Box::new(lhs),
+                rhs: Box::new(rhs),
+            }));
+        }
 
-        let rhs = match self.assert_union(&[
+        Ok(lhs)
+    }
+
+    #[rustfmt::skip]
+    fn parse_operand(&mut self) -> Result<Expr, Trace<'a, SynErr>> {
+        let tok = self.assert_union(&[
             TokKind::Ident, 
             TokKind::OpenerDQuote, 
-            TokKind::IntLit
-        ]) {
-            Ok(tok) => match tok.kind {
-                TokKind::Ident => {
-                    let args = self.parse_args()?;
-                    Expr::Ref(Ref {
-                        name: tok.val_owned(),
-                        args,
-                        ty: Ty::Unknown,
-                    })
-                }
-                TokKind::OpenerDQuote => {
-                    let string = self.assert(TokKind::StringLit)?;
-                    self.assert(TokKind::CloserDQuote)?;
-                    Expr::Lit(Lit::String(string.val_owned()))
+            TokKind::IntLit,
+            TokKind::LParen,
+            TokKind::Bang,
+            TokKind::Minus,
+            TokKind::LSquare,
+        ])?;
+        let mut expr = match tok.kind {
+            TokKind::Ident => {
+                let name = self.parse_ref_name(tok)?;
+                Expr::Ref(Ref {
+                    name,
+                    args: self.parse_args()?,
+                    ty: Ty::Unknown,
+                })
+            }
+            TokKind::OpenerDQuote => {
+                let string = self.assert(TokKind::StringLit)?;
+                self.assert(TokKind::CloserDQuote)?;
+                Expr::Lit(Lit::String(string.val_owned()))
+            }
+            TokKind::IntLit => Expr::Lit(Lit::Int(tok.val_owned())),
+            TokKind::LParen => {
+                let expr = self.parse_expr()?;
+                self.assert(TokKind::RParen)?;
+                expr
+            }
+            TokKind::Bang | TokKind::Minus => Expr::Lit(Lit::UnOp(UnOp {
+                op: if tok.kind == TokKind::Bang { Op::Not } else { Op::Sub },
+                expr: Box::new(self.parse_operand()?),
+            })),
+            TokKind::LSquare => {
+                let mut items = Vec::new();
+                while self.consume_if(TokKind::RSquare).is_err() {
+                    items.push(self.parse_expr()?);
+                    if self.consume_if(TokKind::Comma).is_err() {
+                        self.assert(TokKind::RSquare)?;
+                        break;
+                    }
                 }
-                TokKind::IntLit => Expr::Lit(Lit::Int(tok.val_owned().parse().unwrap())),
-                _ => unreachable!(),
-            },
-            Err(e) => return Err(e),
+                Expr::List(items)
+            }
+            _ => unreachable!(),
         };
-
-        Ok(BinOp {
-            op,
-            lhs: Box::new(lhs),
-            rhs: Box::new(rhs),
-        })
+        while self.consume_if(TokKind::LSquare).is_ok() {
+            let ix = self.parse_expr()?;
+            self.assert(TokKind::RSquare)?;
+            expr = Expr::Lit(Lit::BinOp(BinOp {
+                op: Op::Index,
+                lhs: Box::new(expr),
+                rhs: Box::new(ix),
+            }));
+        }
+        Ok(expr)
     }
 
     fn assert(&mut self, kind: TokKind) -> Result<Token<'a>, Trace<'a, SynErr>> {
@@ -639,7 +1039,10 @@ mod test {
                 assert_eq!(ast.params(), $params);
                 assert_eq!(ast.kind(), crate::ast::AstKind::File);
                 if let crate::ast::Ast::File(f) = ast {
-                    assert_eq!(f.path, $name);
+                    assert_eq!(
+                        f.path,
+                        vec![crate::ast::Expr::Lit(Lit::String($name.into()))]
+                    );
                     assert_eq!(f.alias, $alias);
                     assert_eq!(f.params, $params);
                     assert_eq!(f.content, $content);
@@ -707,7 +1110,11 @@ mod test {
         r#"file foo(bar: str): "voo.a" {{Hello {$bar$}}};"#,
         "voo.a",
         "foo",
-        vec![("bar".into(), crate::ast::Ty::String)],
+        vec![crate::ast::Param {
+            name: "bar".into(),
+            ty: crate::ast::Ty::String,
+            default: None,
+        }],
         vec![
             crate::ast::Expr::Lit(Lit::String("Hello ".into())),
             crate::ast::Expr::Ref(crate::ast::Ref {
@@ -724,7 +1131,11 @@ mod test {
         r#"file foo(bar: str): "voo.a" {$bar$};"#,
         "voo.a",
         "foo",
-        vec![("bar".into(), crate::ast::Ty::String)],
+        vec![crate::ast::Param {
+            name: "bar".into(),
+            ty: crate::ast::Ty::String,
+            default: None,
+        }],
         vec![crate::ast::Expr::Ref(crate::ast::Ref {
             name: "bar".into(),
             args: Vec::new(),
@@ -737,7 +1148,11 @@ mod test {
         r#"file "$foo.a"(bar: str) {$bar$};"#,
         "$foo.a",
         "$foo.a",
-        vec![("bar".into(), crate::ast::Ty::String)],
+        vec![crate::ast::Param {
+            name: "bar".into(),
+            ty: crate::ast::Ty::String,
+            default: None,
+        }],
         vec![crate::ast::Expr::Ref(crate::ast::Ref {
             name: "bar".into(),
             args: Vec::new(),
@@ -837,4 +1252,287 @@ mod test {
         let ast = syn.parse_all();
         assert!(ast.is_ok());
     }
+
+    #[test]
+    fn test_let_for() {
+        let src = r#"let hey = {{Hi @for name in names {{[{$name$}]}}!}};"#;
+
+        println!("TEST: {}", src);
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let ast = syn.parse().expect("Failed to parse");
+        if let crate::ast::Ast::Let(l) = ast {
+            assert_eq!(
+                l.expr,
+                vec![
+                    crate::ast::Expr::Lit(Lit::String("Hi ".into())),
+                    crate::ast::Expr::For(crate::ast::For {
+                        var: "name".into(),
+                        iter: Box::new(crate::ast::Expr::Ref(crate::ast::Ref {
+                            name: "names".into(),
+                            args: Vec::new(),
+                            ty: Ty::Unknown,
+                        })),
+                        body: vec![
+                            crate::ast::Expr::Lit(Lit::String("[".into())),
+                            crate::ast::Expr::Ref(crate::ast::Ref {
+                                name: "name".into(),
+                                args: Vec::new(),
+                                ty: Ty::String,
+                            }),
+                            crate::ast::Expr::Lit(Lit::String("]".into())),
+                        ],
+                    }),
+                    crate::ast::Expr::Lit(Lit::String("!".into())),
+                ]
+            );
+        } else {
+            panic!("Expected let");
+        }
+    }
+
+    #[test]
+    fn test_list() {
+        let src = r#"req names: list<str>; let all = [names[0], "b"];"#;
+
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let ast = syn.parse_all().expect("Failed to parse");
+        assert_eq!(ast[0].ty(), Ty::List(Box::new(Ty::String)));
+        if let crate::ast::Ast::Let(l) = &ast[1] {
+            assert_eq!(
+                l.expr,
+                vec![crate::ast::Expr::List(vec![
+                    crate::ast::Expr::Lit(Lit::BinOp(crate::ast::BinOp {
+                        op: crate::ast::Op::Index,
+                        lhs: Box::new(crate::ast::Expr::Ref(crate::ast::Ref {
+                            name: "names".into(),
+                            args: Vec::new(),
+                            ty: Ty::Unknown,
+                        })),
+                        rhs: Box::new(crate::ast::Expr::Lit(Lit::Int("0".into()))),
+                    })),
+                    crate::ast::Expr::Lit(Lit::String("b".into())),
+                ])]
+            );
+        } else {
+            panic!("Expected let");
+        }
+    }
+
+    #[test]
+    fn test_let_if() {
+        let src =
+            r#"let hey = {{@if name == "kenobi" {{A}} @elseif name != "" {{B}} @else {{C}}}};"#;
+
+        println!("TEST: {}", src);
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let ast = syn.parse().expect("Failed to parse");
+        let name = || {
+            Box::new(crate::ast::Expr::Ref(crate::ast::Ref {
+                name: "name".into(),
+                args: Vec::new(),
+                ty: Ty::Unknown,
+            }))
+        };
+        let string = |s: &str| crate::ast::Expr::Lit(Lit::String(s.into()));
+        if let crate::ast::Ast::Let(l) = ast {
+            assert_eq!(
+                l.expr,
+                vec![
+                    string(""),
+                    crate::ast::Expr::If(crate::ast::If {
+                        cond: Box::new(crate::ast::Expr::Lit(Lit::BinOp(crate::ast::BinOp {
+                            op: crate::ast::Op::Eq,
+                            lhs: name(),
+                            rhs: Box::new(string("kenobi")),
+                        }))),
+                        then: vec![string("A")],
+                        els: vec![crate::ast::Expr::If(crate::ast::If {
+                            cond: Box::new(crate::ast::Expr::Lit(Lit::BinOp(crate::ast::BinOp {
+                                op: crate::ast::Op::Neq,
+                                lhs: name(),
+                                rhs: Box::new(string("")),
+                            }))),
+                            then: vec![string("B")],
+                            els: vec![string("C")],
+                        })],
+                    }),
+                    string(""),
+                ]
+            );
+        } else {
+            panic!("Expected let");
+        }
+    }
+
+    #[test]
+    fn test_dir_children() {
+        let src = r#"
+            dir foo {
+                src { "main.rs": {{fn main();}} },
+                "Cargo.toml"
+                @if ci { ".github" { workflows } } @else { @nothing }
+                @for svc in services { "svc.rs": @svc_tpl(name: svc) }
+            }
+        "#;
+
+        println!("TEST: {}", src);
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let ast = syn.parse().expect("Failed to parse");
+        let children = match ast {
+            crate::ast::Ast::Dir(d) => d.children,
+            _ => panic!("Expected dir"),
+        };
+        assert_eq!(children.len(), 4);
+        assert!(
+            matches!(&children[0], crate::ast::Expr::Lit(Lit::Dir(d)) if d.children.len() == 1)
+        );
+        assert!(
+            matches!(&children[1], crate::ast::Expr::Lit(Lit::File(f)) if f.alias == "Cargo.toml")
+        );
+        match &children[2] {
+            crate::ast::Expr::If(i) => {
+                assert!(
+                    matches!(&i.then[..], [crate::ast::Expr::Lit(Lit::Dir(d))] if d.alias == ".github")
+                );
+                assert!(matches!(&i.els[..], [crate::ast::Expr::Ref(r)] if r.name == "nothing"));
+            }
+            _ => panic!("Expected if"),
+        }
+        match &children[3] {
+            crate::ast::Expr::For(f) => {
+                assert_eq!(f.var, "svc");
+                assert!(
+                    matches!(&f.body[..], [crate::ast::Expr::Lit(Lit::File(f))] if f.alias == "svc.rs")
+                );
+            }
+            _ => panic!("Expected for"),
+        }
+    }
+
+    #[test]
+    fn test_expr_precedence() {
+        let src = r#"a || b && !c == 1 + 2 * (3 - d)"#;
+
+        println!("TEST: {}", src);
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let expr = syn.parse_expr().expect("Failed to parse");
+        assert_eq!(
+            crate::exec::dump::Dump::dump(&expr),
+            "(@a || (@b && ((!@c) == (1 + (2 * (3 - @d))))))"
+        );
+    }
+
+    #[test]
+    fn test_cfg() {
+        let src = r#"cfg { [indent=4s], [eol=crlf] [final_newline=true] [out=../build] }"#;
+
+        println!("TEST: {}", src);
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let ast = syn.parse().expect("Failed to parse");
+        assert_eq!(
+            ast,
+            crate::ast::Ast::Cfg(crate::ast::Cfg {
+                indent: Some("    ".into()),
+                eol: Some(crate::ast::Eol::Crlf),
+                final_newline: Some(true),
+                on_conflict: None,
+                dedent: None,
+                reindent: None,
+                out: Some("../build".into()),
+            })
+        );
+
+        let mut syn = crate::syntax::Syntax::new(b"cfg { [indent=4x] }");
+        assert!(syn.parse().is_err());
+        let mut syn = crate::syntax::Syntax::new("cfg { [indent=\"é\"] }".as_bytes());
+        assert!(syn.parse().is_err());
+    }
+
+    #[test]
+    fn test_annots() {
+        let src = r#"
+            [lang=go]
+            [mode=0o755, skip_if=!ci]
+            main dir out {
+                [mode=644] "run.sh",
+                [skip_if=name == "x"] docs
+            }
+        "#;
+
+        println!("TEST: {}", src);
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let dir = match syn.parse().expect("Failed to parse") {
+            crate::ast::Ast::Dir(dir) => dir,
+            ast => panic!("Expected dir, got {:?}", ast),
+        };
+        assert!(dir.main);
+        assert_eq!(
+            crate::exec::dump::Dump::dump(&dir),
+            r##"[lang=go] [mode=0o755] [skip_if=(!@ci)] main dir out: out {[mode=644] "run.sh": r#{{}}#, [skip_if=(@name == "x")] "docs" {} }"##
+        );
+
+        let mut syn = crate::syntax::Syntax::new(b"[lang=go] req name: str;");
+        assert!(syn.parse().is_err());
+    }
+
+    #[test]
+    fn test_doc_comments() {
+        let src = r#"
+            /// The project name.
+            req name: str; // not a doc
+            /* block */
+            /// Entry point,
+            /// generated once.
+            [lang=go]
+            main let body = {{// kept {$ name $} /* too */}};
+        "#;
+
+        println!("TEST: {}", src);
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let asts = syn.parse_all().expect("Failed to parse");
+        match &asts[..] {
+            [crate::ast::Ast::Req(req), crate::ast::Ast::Let(body)] => {
+                assert_eq!(req.doc.as_deref(), Some("The project name."));
+                assert_eq!(body.doc.as_deref(), Some("Entry point,\ngenerated once."));
+                assert!(body.main);
+                assert_eq!(
+                    body.expr[0],
+                    crate::ast::Expr::Lit(Lit::String("// kept ".into()))
+                );
+                assert_eq!(
+                    body.expr[2],
+                    crate::ast::Expr::Lit(Lit::String(" /* too */".into()))
+                );
+            }
+            asts => panic!("Expected req and let, got {:?}", asts),
+        }
+    }
+
+    #[test]
+    fn test_escapes() {
+        let src = r####"
+            let dq = "say \"hi\"\n\{$ x $}";
+            let go = {{fmt.Print("\n") \{$ x $\} \@for {}}};
+            let hbs = r#{{<b>{{ name }}</b> {$ x $}}}#;
+            let nested = r##{{ }}# }}##;
+        "####;
+
+        println!("TEST: {}", src);
+        let mut syn = crate::syntax::Syntax::new(src.as_bytes());
+        let bodies = syn
+            .parse_all()
+            .expect("Failed to parse")
+            .into_iter()
+            .map(|ast| match ast {
+                crate::ast::Ast::Let(l) => l.expr,
+                ast => panic!("Expected let, got {:?}", ast),
+            })
+            .collect::<Vec<_>>();
+        let lit = |s: &str| vec![crate::ast::Expr::Lit(Lit::String(s.into()))];
+        assert_eq!(bodies[0], lit("say \"hi\"\n{$ x $}"));
+        assert_eq!(bodies[1], lit("fmt.Print(\"\\n\") {$ x $} @for {}"));
+        let raw = |s: &str| vec![crate::ast::Expr::Lit(Lit::Raw(s.into()))];
+        assert_eq!(bodies[2], raw("<b>{{ name }}</b> {$ x $}"));
+        assert_eq!(bodies[3], raw(" }}# "));
+    }
 }
@@ -48,28 +48,29 @@ impl<'a> Token<'a> {
     #[rustfmt::skip]
     pub fn is_closer(&self) -> bool {
         use TokKind::*;
-        match self.kind {
+        matches!(
+            self.kind,
             CloserDQuote 
             | RCurlyDollar 
             | RCurlyDQuote 
             | RCurly 
             | RSquare 
-            | RParen => true,
-            _ => false,
-        }
+            | RParen
+        )
     }
 
+    #[rustfmt::skip]
     pub fn is_opener(&self) -> bool {
         use TokKind::*;
-        match self.kind {
+        matches!(
+            self.kind,
             OpenerDQuote 
             | LCurly 
             | LCurlyDollar 
             | LCurlyDQuote 
             | LSquare 
-            | LParen => true,
-            _ => false,
-        }
+            | LParen
+        )
     }
 
     /// Converts the src to a string slice
@@ -89,13 +90,7 @@ impl<'a> Token<'a> {
 
 impl std::fmt::Display for Token<'_> {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        return write!(
-            f,
-            "`{}` = `{}` :: [{}]",
-            self.kind,
-            self.val(),
-            self.src.to_string()
-        );
+        write!(f, "`{}` = `{}` :: [{}]", self.kind, self.val(), self.src)
     }
 }
 
@@ -112,6 +107,9 @@ pub enum TokKind {
     If,
     Else,
     ElseIf,
+    Use,
+    As,
+    Cfg,
     StringKw,
     ListKw,
 
@@ -122,10 +120,12 @@ pub enum TokKind {
     Pound,
     Backslash,
     Colon,
+    DoubleColon,
     Semi,
     Comma,
     Eq,
     Dot,
+    Question,
 
     // Openers
     OpenerDQuote,
@@ -152,9 +152,18 @@ pub enum TokKind {
     Slash,
     DoubleEq,
     Neq,
+    Lt,
+    Gt,
+    Le,
+    Ge,
+    And,
+    Or,
+    Pipe,
 
     // Literals
     StringLit,
+    /// The body of `r#{{ .. }}#`, kept as written.
+    RawStringLit,
     IntLit,
 
     Ident,
@@ -175,6 +184,9 @@ impl std::fmt::Display for TokKind {
             TokKind::If => write!(f, "If"),
             TokKind::Else => write!(f, "Else"),
             TokKind::ElseIf => write!(f, "ElseIf"),
+            TokKind::Use => write!(f, "Use"),
+            TokKind::As => write!(f, "As"),
+            TokKind::Cfg => write!(f, "Cfg"),
             TokKind::StringKw => write!(f, "String (Keyword)"),
             TokKind::ListKw => write!(f, "List (Keyword)"),
 
@@ -182,9 +194,11 @@ impl std::fmt::Display for TokKind {
             TokKind::Bang => write!(f, "!"),
             TokKind::Backslash => write!(f, "\\"),
             TokKind::Colon => write!(f, ":"),
+            TokKind::DoubleColon => write!(f, "::"),
             TokKind::Semi => write!(f, ";"),
             TokKind::Comma => write!(f, ","),
             TokKind::Dot => write!(f, "."),
+            TokKind::Question => write!(f, "?"),
             TokKind::Dollar => write!(f, "$"),
             TokKind::Pound => write!(f, "#"),
 
@@ -211,8 +225,16 @@ impl std::fmt::Display for TokKind {
             TokKind::Eq => write!(f, "="),
             TokKind::DoubleEq => write!(f, "=="),
             TokKind::Neq => write!(f, "!="),
+            TokKind::Lt => write!(f, "<"),
+            TokKind::Gt => write!(f, ">"),
+            TokKind::Le => write!(f, "<="),
+            TokKind::Ge => write!(f, ">="),
+            TokKind::And => write!(f, "&&"),
+            TokKind::Or => write!(f, "||"),
+            TokKind::Pipe => write!(f, "|"),
 
             TokKind::StringLit => write!(f, "String (Literal)"),
+            TokKind::RawStringLit => write!(f, "Raw String (Literal)"),
             TokKind::IntLit => write!(f, "Int (Literal)"),
 
             TokKind::Ident => write!(f, "Identifier"),
@@ -234,6 +256,7 @@ impl From<u8> for TokKind {
             b';' => TokKind::Semi,
             b',' => TokKind::Comma,
             b'.' => TokKind::Dot,
+            b'?' => TokKind::Question,
 
             b'{' => TokKind::LCurly,
             b'[' => TokKind::LSquare,
@@ -248,7 +271,10 @@ impl From<u8> for TokKind {
             b'*' => TokKind::Star,
             b'/' => TokKind::Slash,
             b'=' => TokKind::Eq,
-            
+            b'<' => TokKind::Lt,
+            b'>' => TokKind::Gt,
+            b'|' => TokKind::Pipe,
+
             b'\0' => TokKind::EOF,
             _ => TokKind::Invalid,
         }
@@ -268,6 +294,9 @@ impl From<&str> for TokKind {
             "if" => TokKind::If,
             "else" => TokKind::Else,
             "elseif" => TokKind::ElseIf,
+            "use" => TokKind::Use,
+            "as" => TokKind::As,
+            "cfg" => TokKind::Cfg,
             "str" => TokKind::StringKw,
             "list" => TokKind::ListKw,
 
@@ -278,11 +307,24 @@ impl From<&str> for TokKind {
             "/" => TokKind::Slash,
             "\\" => TokKind::Backslash,
             ":" => TokKind::Colon,
+            "::" => TokKind::DoubleColon,
             ";" => TokKind::Semi,
             "," => TokKind::Comma,
             "." => TokKind::Dot,
+            "?" => TokKind::Question,
             "=" => TokKind::Eq,
+            "+" => TokKind::Plus,
+            "-" => TokKind::Minus,
+            "*" => TokKind::Star,
             "==" => TokKind::DoubleEq,
+            "!=" => TokKind::Neq,
+            "<" => TokKind::Lt,
+            ">" => TokKind::Gt,
+            "<=" => TokKind::Le,
+            ">=" => TokKind::Ge,
+            "&&" => TokKind::And,
+            "||" => TokKind::Or,
+            "|" => TokKind::Pipe,
 
             "{" => TokKind::LCurly,
             "{{" => TokKind::LCurlyDouble,
@@ -430,6 +472,18 @@ mod token_tests {
         assert_eq!(TokKind::from("="), TokKind::Eq);
     }
 
+    #[test]
+    fn test_operators() {
+        assert_eq!(TokKind::from("=="), TokKind::DoubleEq);
+        assert_eq!(TokKind::from("!="), TokKind::Neq);
+        assert_eq!(TokKind::from("<"), TokKind::Lt);
+        assert_eq!(TokKind::from(">"), TokKind::Gt);
+        assert_eq!(TokKind::from("<="), TokKind::Le);
+        assert_eq!(TokKind::from(">="), TokKind::Ge);
+        assert_eq!(TokKind::from("&&"), TokKind::And);
+        assert_eq!(TokKind::from("||"), TokKind::Or);
+    }
+
     #[test]
     fn test_openers() {
         assert_eq!(TokKind::from("{"), TokKind::LCurly);
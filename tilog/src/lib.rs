@@ -211,6 +211,7 @@ impl PartialOrd for Level {
 }
 
 impl Level {
+    #[allow(clippy::should_implement_trait)]
     pub fn from_str(s: &str) -> Level {
         match s.to_uppercase().as_str() {
             "INFO" => Level::Info,
@@ -336,7 +337,7 @@ const fn reset_code(reset: Reset) -> &'static str {
     }
 }
 
-pub fn dump<'a>(level: Level, stage: Stage, msg: std::fmt::Arguments) {
+pub fn dump(level: Level, stage: Stage, msg: std::fmt::Arguments) {
     if get_level() >= level {
         let conf = unsafe {
             #[cfg(feature = "mt")]
@@ -345,7 +346,7 @@ pub fn dump<'a>(level: Level, stage: Stage, msg: std::fmt::Arguments) {
             }
             #[cfg(not(feature = "mt"))]
             {
-                &CONF
+                &*std::ptr::addr_of!(CONF)
             }
         };
 
@@ -367,10 +368,10 @@ pub fn dump<'a>(level: Level, stage: Stage, msg: std::fmt::Arguments) {
 
         if conf.emoji {
             println!(
-                "{}{}{} {}> {}{}",
+                "{}{}[{}] {}> {}{}",
                 style,
                 color,
-                format!("[{}]", level.emoji()),
+                level.emoji(),
                 if stage != Stage::None {
                     format!("<{}", stage.emoji())
                 } else {
@@ -384,9 +385,9 @@ pub fn dump<'a>(level: Level, stage: Stage, msg: std::fmt::Arguments) {
                 "{}{}{: <5} {}> {}{}",
                 style,
                 color,
-                format!("{}", level),
+                level.to_string(),
                 if stage != Stage::None {
-                    format!("<{}", stage.to_string())
+                    format!("<{}", stage)
                 } else {
                     "".to_string()
                 },